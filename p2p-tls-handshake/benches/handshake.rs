@@ -0,0 +1,143 @@
+//! Benchmarks of the TLS handshake performed by the security upgrade.
+//!
+//! Both peers run in the same thread over an in-memory connection, so the measured time is the CPU
+//! time of the handshake itself: certificate verification, handshake signatures and key exchange.
+//! The `handshake` group compares handshakes with a peer whose certificate was verified before,
+//! with and without the verification cache of the configuration. The `reconnect` group compares
+//! reconnecting to a peer with and without resuming the session.
+//! Compare against an earlier revision with `cargo bench -- --save-baseline <name>` and
+//! `cargo bench -- --baseline <name>`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use futures::{executor::block_on, future, AsyncReadExt, AsyncWriteExt};
+use libp2p_core::{
+    multiaddr::Protocol,
+    transport::{ListenerId, MemoryTransport, Transport, TransportEvent},
+};
+use libp2p_identity::{Keypair, PeerId};
+use p2p_tls_handshake::{Config, InboundSecurityUpgrade, OutboundSecurityUpgrade, SessionCache};
+use std::{pin::Pin, time::Duration};
+
+/// Performs a handshake between `dialer` and `listener` over a fresh in-memory connection.
+fn handshake(dialer: Config, listener: Config) {
+    connect(dialer, listener, None);
+}
+
+/// Connects `dialer` to `listener`, whose peer ID the dialer expects to be `peer_id`, and sends a
+/// message from the listener to the dialer, which also delivers the session tickets.
+fn reconnect(dialer: Config, listener: Config, peer_id: PeerId) {
+    connect(dialer, listener, Some(peer_id));
+}
+
+fn connect(dialer: Config, listener: Config, peer_id: Option<PeerId>) {
+    block_on(async {
+        let mut transport = MemoryTransport::default();
+        transport
+            .listen_on(ListenerId::next(), Protocol::Memory(0).into())
+            .unwrap();
+        let addr = match future::poll_fn(|cx| Pin::new(&mut transport).poll(cx)).await {
+            TransportEvent::NewAddress { listen_addr, .. } => listen_addr,
+            _ => panic!("expected a new listen address"),
+        };
+
+        let dial = MemoryTransport::default().dial(addr).unwrap();
+        let accept = async {
+            match future::poll_fn(|cx| Pin::new(&mut transport).poll(cx)).await {
+                TransportEvent::Incoming { upgrade, .. } => upgrade.await,
+                _ => panic!("expected an incoming connection"),
+            }
+        };
+        let (dialer_conn, listener_conn) = future::join(dial, accept).await;
+
+        let (dialer, listener) = future::join(
+            dialer.secure_outbound(dialer_conn.unwrap(), "/tls/1.0.0", peer_id),
+            listener.secure_inbound(listener_conn.unwrap(), "/tls/1.0.0"),
+        )
+        .await;
+        let (_, mut dialer) = dialer.unwrap();
+        let (_, mut listener) = listener.unwrap();
+
+        if peer_id.is_some() {
+            listener.write_all(b"ping").await.unwrap();
+            listener.flush().await.unwrap();
+            dialer.read_exact(&mut [0; 4]).await.unwrap();
+        }
+    })
+}
+
+fn bench_handshake(c: &mut Criterion) {
+    let mut group = c.benchmark_group("handshake");
+
+    for (name, keypair) in [
+        ("ed25519", Keypair::generate_ed25519()),
+        ("ecdsa", Keypair::generate_ecdsa()),
+        ("secp256k1", Keypair::generate_secp256k1()),
+    ] {
+        let listener = Config::new(&keypair).unwrap();
+
+        // Every handshake presents a certificate the listener has never seen.
+        group.bench_function(format!("{name}/new_peer"), |b| {
+            b.iter_batched(
+                || Config::new(&Keypair::generate_ed25519()).unwrap(),
+                |dialer| handshake(dialer, listener.clone()),
+                BatchSize::SmallInput,
+            )
+        });
+
+        // Every handshake presents the same certificates.
+        let dialer = Config::new(&Keypair::generate_ed25519()).unwrap();
+        group.bench_function(format!("{name}/known_peer"), |b| {
+            b.iter(|| handshake(dialer.clone(), listener.clone()))
+        });
+
+        // Every handshake presents the same certificates, to peers that verify them again.
+        // Changing a setting of the verifier, even to its default, starts with an empty cache.
+        group.bench_function(format!("{name}/known_peer_uncached"), |b| {
+            b.iter_batched(
+                || {
+                    (
+                        dialer.clone().with_clock_skew(Duration::ZERO),
+                        listener.clone().with_clock_skew(Duration::ZERO),
+                    )
+                },
+                |(dialer, listener)| handshake(dialer, listener),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_reconnect(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reconnect");
+
+    for (name, keypair) in [
+        ("ed25519", Keypair::generate_ed25519()),
+        ("ecdsa", Keypair::generate_ecdsa()),
+        ("secp256k1", Keypair::generate_secp256k1()),
+    ] {
+        let peer_id = keypair.public().to_peer_id();
+        let listener = Config::new(&keypair).unwrap();
+
+        // Every reconnect performs a full handshake, as the dialer keeps no sessions.
+        let dialer = Config::new(&Keypair::generate_ed25519())
+            .unwrap()
+            .with_session_cache(SessionCache::new(0, Duration::ZERO));
+        group.bench_function(format!("{name}/full_handshake"), |b| {
+            b.iter(|| reconnect(dialer.clone(), listener.clone(), peer_id))
+        });
+
+        // Every reconnect resumes the session of the previous one.
+        let dialer = Config::new(&Keypair::generate_ed25519()).unwrap();
+        reconnect(dialer.clone(), listener.clone(), peer_id);
+        group.bench_function(format!("{name}/resumed"), |b| {
+            b.iter(|| reconnect(dialer.clone(), listener.clone(), peer_id))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_handshake, bench_reconnect);
+criterion_main!(benches);
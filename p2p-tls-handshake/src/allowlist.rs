@@ -0,0 +1,368 @@
+//! Peer allowlists backed by a file
+//!
+//! A [`FileAllowlist`] admits only the peers listed in a file, one peer ID per line. Blank lines
+//! and lines starting with `#` are ignored. The file can be reloaded at any time, either
+//! explicitly or by a background watcher, and the new list replaces the old one atomically, so
+//! that concurrent handshakes see either the old or the new list but never a mix of both.
+//!
+//! A reload may read the file while it is being written. It keeps the current list when the file
+//! changes while being read, is empty, as it is right after being truncated, or when its last line
+//! is cut in the middle of a peer ID, but a file cut at the end of a line cannot be told apart
+//! from a shorter list. The watcher therefore only reloads a file once it stopped changing, see
+//! [`FileAllowlist::watch`]. An allowlist that admits no peer needs at least a comment line.
+
+use crate::error::AllowlistError;
+use crate::policy::PeerPolicy;
+use libp2p_identity::{PeerId, PublicKey};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use std::{fs, io, thread};
+
+/// A peer allowlist loaded from a file.
+///
+/// Handles are cheap to clone and share the same list, so one handle can be passed to
+/// [`Config::with_policy`](crate::Config::with_policy) while another one reloads the file.
+#[derive(Debug, Clone)]
+pub struct FileAllowlist {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path: PathBuf,
+    peers: RwLock<Arc<HashSet<PeerId>>>,
+}
+
+impl FileAllowlist {
+    /// Loads the allowlist from the file at `path`.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, AllowlistError> {
+        Self::open(path.into()).map(|(allowlist, _)| allowlist)
+    }
+
+    /// Loads the allowlist from the file at `path` and checks it every `interval` from a
+    /// background thread, which stops once every handle has been dropped.
+    ///
+    /// A change is only reloaded once the file stayed the same for a whole `interval`, so that a
+    /// file being written is not mistaken for a shorter list. The file should be updated by
+    /// writing a new file next to it and renaming it over the old one. Writing the file in place
+    /// is only safe if every write completes within `interval`.
+    pub fn watch(path: impl Into<PathBuf>, interval: Duration) -> Result<Self, AllowlistError> {
+        let (allowlist, loaded) = Self::open(path.into())?;
+        let inner = Arc::downgrade(&allowlist.inner);
+        thread::Builder::new()
+            .name("allowlist-watcher".into())
+            .spawn(move || watch(inner, interval, Watcher::new(loaded)))?;
+
+        Ok(allowlist)
+    }
+
+    /// Reads the file again and replaces the list.
+    ///
+    /// If the file cannot be read, changes while being read, is empty or contains an invalid peer
+    /// ID, the current list is kept. Unlike the watcher, this reads the file right away, so it
+    /// should only be called once the file has been completely written.
+    pub fn reload(&self) -> Result<(), AllowlistError> {
+        self.inner.reload().map(|_| ())
+    }
+
+    fn open(path: PathBuf) -> Result<(Self, Stamp), AllowlistError> {
+        let (contents, stamp) = read(&path)?;
+        let peers = parse(&contents)?;
+        let allowlist = Self {
+            inner: Arc::new(Inner {
+                path,
+                peers: RwLock::new(Arc::new(peers)),
+            }),
+        };
+
+        Ok((allowlist, stamp))
+    }
+
+    /// The path of the allowlist file.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// The peers currently admitted.
+    pub fn peers(&self) -> Arc<HashSet<PeerId>> {
+        self.inner
+            .peers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl Inner {
+    /// Replaces the list with the contents of the file, returning the stamp of the file read.
+    fn reload(&self) -> Result<Stamp, AllowlistError> {
+        let (contents, stamp) = read(&self.path)?;
+        // A file being rewritten in place is empty for a moment.
+        if contents.trim().is_empty() {
+            return Err(AllowlistError::Empty);
+        }
+        let peers = parse(&contents)?;
+
+        let mut current = self.peers.write().unwrap_or_else(PoisonError::into_inner);
+        if **current != peers {
+            *current = Arc::new(peers);
+            tracing::info!(path=?self.path, peers=current.len(), "Reloaded allowlist");
+        }
+
+        Ok(stamp)
+    }
+}
+
+/// The metadata of a file that changes whenever it is written or replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stamp {
+    len: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: std::os::unix::fs::MetadataExt::ino(&metadata),
+        })
+    }
+}
+
+/// Decides when the watcher reloads the file.
+struct Watcher {
+    /// The stamp of the file the current list was read from.
+    loaded: Stamp,
+    /// The stamp of the file at the previous check.
+    seen: Option<Stamp>,
+}
+
+impl Watcher {
+    fn new(loaded: Stamp) -> Self {
+        Self { loaded, seen: None }
+    }
+
+    /// Reloads the file if it changed since it was last loaded, but not since the previous check.
+    fn check(&mut self, inner: &Inner) -> Result<(), AllowlistError> {
+        let stamp = Stamp::of(&inner.path)?;
+        let settled = self.seen.as_ref() == Some(&stamp);
+        if stamp != self.loaded && settled {
+            self.loaded = inner.reload()?;
+        }
+        self.seen = Some(stamp);
+
+        Ok(())
+    }
+}
+
+impl PeerPolicy for FileAllowlist {
+    fn admit(&self, public_key: &PublicKey) -> bool {
+        let peer_id = public_key.to_peer_id();
+        if self.peers().contains(&peer_id) {
+            return true;
+        }
+        tracing::warn!(%peer_id, path=?self.inner.path, "Rejected peer missing from allowlist");
+
+        false
+    }
+}
+
+/// Reads the file at `path`, failing if it changes meanwhile.
+fn read(path: &Path) -> Result<(String, Stamp), AllowlistError> {
+    let stamp = Stamp::of(path)?;
+    let contents = fs::read_to_string(path)?;
+    if Stamp::of(path)? != stamp {
+        return Err(AllowlistError::Changed);
+    }
+
+    Ok((contents, stamp))
+}
+
+fn parse(contents: &str) -> Result<HashSet<PeerId>, AllowlistError> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, peer_id)| {
+            peer_id
+                .parse()
+                .map_err(|source| AllowlistError::InvalidPeerId { line, source })
+        })
+        .collect()
+}
+
+fn watch(inner: Weak<Inner>, interval: Duration, mut watcher: Watcher) {
+    loop {
+        thread::sleep(interval);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        if let Err(e) = watcher.check(&inner) {
+            tracing::warn!(path=?inner.path, "Failed to reload allowlist, keeping the current one: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_identity::Keypair;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Instant;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("p2p-tls-handshake-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn load_skips_blank_lines_and_comments() {
+        let admitted = Keypair::generate_ed25519().public();
+        let path = temp_path("allowlist-load");
+        fs::write(
+            &path,
+            format!("# Bootstrap nodes\n\n  {}  \n", admitted.to_peer_id()),
+        )
+        .unwrap();
+
+        let allowlist = FileAllowlist::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(allowlist.peers().len(), 1);
+        assert!(allowlist.admit(&admitted));
+        assert!(!allowlist.admit(&Keypair::generate_ed25519().public()));
+    }
+
+    #[test]
+    fn load_rejects_invalid_peer_id() {
+        let path = temp_path("allowlist-invalid");
+        fs::write(&path, format!("{}\nnot-a-peer-id\n", PeerId::random())).unwrap();
+
+        let result = FileAllowlist::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(
+            result,
+            Err(AllowlistError::InvalidPeerId { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn reload_replaces_list() {
+        let first = Keypair::generate_ed25519().public();
+        let second = Keypair::generate_ed25519().public();
+        let path = temp_path("allowlist-reload");
+        fs::write(&path, first.to_peer_id().to_string()).unwrap();
+        let allowlist = FileAllowlist::load(&path).unwrap();
+
+        fs::write(&path, second.to_peer_id().to_string()).unwrap();
+        allowlist.reload().unwrap();
+
+        assert!(!allowlist.admit(&first));
+        assert!(allowlist.admit(&second));
+
+        fs::write(&path, "not-a-peer-id").unwrap();
+        assert!(allowlist.reload().is_err());
+        fs::remove_file(&path).unwrap();
+
+        assert!(allowlist.admit(&second));
+    }
+
+    #[test]
+    fn reload_keeps_list_while_file_is_rewritten() {
+        let admitted = Keypair::generate_ed25519().public();
+        let contents = format!("{}\n", admitted.to_peer_id());
+        let path = temp_path("allowlist-rewritten");
+        fs::write(&path, &contents).unwrap();
+        let allowlist = FileAllowlist::load(&path).unwrap();
+
+        // Every reload reads the file before, while or after it is truncated and written again.
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = thread::spawn({
+            let (path, done) = (path.clone(), done.clone());
+            move || {
+                while !done.load(Ordering::Relaxed) {
+                    fs::write(&path, &contents).unwrap();
+                }
+            }
+        });
+        for _ in 0..1000 {
+            let _ = allowlist.reload();
+            assert!(allowlist.admit(&admitted));
+        }
+        done.store(true, Ordering::Relaxed);
+        writer.join().unwrap();
+
+        fs::write(&path, "").unwrap();
+        assert!(matches!(allowlist.reload(), Err(AllowlistError::Empty)));
+        fs::write(&path, &format!("{}", admitted.to_peer_id())[..10]).unwrap();
+        assert!(matches!(
+            allowlist.reload(),
+            Err(AllowlistError::InvalidPeerId { line: 1, .. })
+        ));
+        fs::remove_file(&path).unwrap();
+
+        assert!(allowlist.admit(&admitted));
+    }
+
+    #[test]
+    fn watcher_waits_for_file_to_settle() {
+        let [first, second, third] = [(); 3].map(|()| Keypair::generate_ed25519().public());
+        let path = temp_path("allowlist-settle");
+        fs::write(
+            &path,
+            format!("{}\n{}\n", first.to_peer_id(), second.to_peer_id()),
+        )
+        .unwrap();
+        let (allowlist, loaded) = FileAllowlist::open(path.clone()).unwrap();
+        let mut watcher = Watcher::new(loaded);
+
+        // The file is cut at the end of a line while being written in place.
+        let update = format!(
+            "{}\n{}\n{}\n",
+            first.to_peer_id(),
+            second.to_peer_id(),
+            third.to_peer_id()
+        );
+        fs::write(&path, &update[..update.find('\n').unwrap() + 1]).unwrap();
+        watcher.check(&allowlist.inner).unwrap();
+        assert!(allowlist.admit(&second));
+
+        fs::write(&path, &update).unwrap();
+        watcher.check(&allowlist.inner).unwrap();
+        assert!(!allowlist.admit(&third));
+
+        watcher.check(&allowlist.inner).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(allowlist.admit(&second));
+        assert!(allowlist.admit(&third));
+    }
+
+    #[test]
+    fn watch_picks_up_changes() {
+        let first = Keypair::generate_ed25519().public();
+        let second = Keypair::generate_ed25519().public();
+        let path = temp_path("allowlist-watch");
+        fs::write(&path, first.to_peer_id().to_string()).unwrap();
+        let allowlist = FileAllowlist::watch(&path, Duration::from_millis(10)).unwrap();
+
+        fs::write(&path, second.to_peer_id().to_string()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !allowlist.peers().contains(&second.to_peer_id()) {
+            assert!(Instant::now() < deadline, "watcher did not reload the file");
+            thread::sleep(Duration::from_millis(10));
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert!(!allowlist.admit(&first));
+    }
+}
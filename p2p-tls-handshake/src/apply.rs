@@ -132,7 +132,7 @@ where
                     }
                 }
                 InboundUpgradeApplyState::Undefined => {
-                    panic!("InboundUpgradeApplyState::poll called after completion")
+                    return Poll::Ready(Err(UpgradeError::PolledAfterCompletion));
                 }
             }
         }
@@ -214,9 +214,88 @@ where
                     }
                 }
                 OutboundUpgradeApplyState::Undefined => {
-                    panic!("OutboundUpgradeApplyState::poll called after completion")
+                    return Poll::Ready(Err(UpgradeError::PolledAfterCompletion));
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::Passthrough;
+    use futures::{executor::block_on, io::Cursor};
+    use std::io;
+
+    /// A peer that sends canned `input` and records what it receives.
+    struct CannedPeer {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl AsyncRead for CannedPeer {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for CannedPeer {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.output).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            AsyncWrite::poll_flush(Pin::new(&mut self.output), cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            AsyncWrite::poll_close(Pin::new(&mut self.output), cx)
+        }
+    }
+
+    #[test]
+    fn inbound_fails_when_polled_after_completion() {
+        // The multistream-select header and the proposal of `/passthrough/1.0.0`.
+        let input = b"\x13/multistream/1.0.0\n\x13/passthrough/1.0.0\n".to_vec();
+        let peer = CannedPeer {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+        let mut upgrade = apply_inbound(peer, Passthrough);
+
+        block_on(async {
+            assert!((&mut upgrade).await.is_ok());
+            assert!(matches!(
+                (&mut upgrade).await,
+                Err(UpgradeError::PolledAfterCompletion)
+            ));
+        });
+    }
+
+    #[test]
+    fn outbound_fails_when_polled_after_completion() {
+        // A lazy dialer settles on its only protocol without waiting for the listener.
+        let peer = CannedPeer {
+            input: Cursor::new(Vec::new()),
+            output: Vec::new(),
+        };
+        let mut upgrade = apply_outbound(peer, Passthrough, Version::V1Lazy);
+
+        block_on(async {
+            assert!((&mut upgrade).await.is_ok());
+            assert!(matches!(
+                (&mut upgrade).await,
+                Err(UpgradeError::PolledAfterCompletion)
+            ));
+        });
+    }
+}
@@ -4,6 +4,7 @@
 
 use libp2p_identity as identity;
 use libp2p_identity::PeerId;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use x509_parser::{prelude::*, signature_algorithm::SignatureAlgorithm};
 
 /// The libp2p Public Key Extension is a X.509 extension
@@ -11,40 +12,164 @@ use x509_parser::{prelude::*, signature_algorithm::SignatureAlgorithm};
 /// allocated by IANA to the libp2p project at Protocol Labs.
 const P2P_EXT_OID: &[u64] = &[1, 3, 6, 1, 4, 1, 53594, 1, 1];
 
-/// The peer signs the concatenation of the string `p2p-tls-handshake:`
+/// The peer signs the concatenation of the string `libp2p-tls-handshake:`
 /// and the public key that it used to generate the certificate carrying
 /// the libp2p Public Key Extension, using its private host key.
 /// This signature provides cryptographic proof that the peer was
 /// in possession of the private host key at the time the certificate was signed.
-const P2P_SIGNING_PREFIX: &[u8] = b"p2p-tls-handshake:";
+const P2P_SIGNING_PREFIX: &[u8] = b"libp2p-tls-handshake:";
 
-// Certificates MUST use the NamedCurve encoding for elliptic curve parameters.
-// Similarly, hash functions with an output length less than 256 bits MUST NOT be used.
-static P2P_SIGNATURE_ALGORITHM: &rcgen::SignatureAlgorithm = &rcgen::PKCS_ECDSA_P256_SHA256;
+/// The algorithm of the certificate keypair, which signs the certificate and the handshake.
+///
+/// Certificates MUST use the NamedCurve encoding for elliptic curve parameters.
+/// Similarly, hash functions with an output length less than 256 bits MUST NOT be used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CertificateAlgorithm {
+    /// ECDSA on the P-256 curve with SHA-256.
+    #[default]
+    EcdsaP256Sha256,
+    /// ECDSA on the P-384 curve with SHA-384.
+    EcdsaP384Sha384,
+    /// Ed25519.
+    Ed25519,
+}
+
+impl CertificateAlgorithm {
+    fn signature_algorithm(self) -> &'static rcgen::SignatureAlgorithm {
+        match self {
+            CertificateAlgorithm::EcdsaP256Sha256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            CertificateAlgorithm::EcdsaP384Sha384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+            CertificateAlgorithm::Ed25519 => &rcgen::PKCS_ED25519,
+        }
+    }
+
+    fn from_signature_algorithm(algorithm: &rcgen::SignatureAlgorithm) -> Option<Self> {
+        [
+            CertificateAlgorithm::EcdsaP256Sha256,
+            CertificateAlgorithm::EcdsaP384Sha384,
+            CertificateAlgorithm::Ed25519,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.signature_algorithm() == algorithm)
+    }
+}
+
+/// Parameters of the certificates generated by [`generate_with_params`].
+///
+/// By default, a new ECDSA P-256 certificate keypair is generated for every certificate, and the
+/// validity period and serial number are the ones assigned by `rcgen`.
+#[derive(Clone, Default)]
+pub struct CertificateParams {
+    algorithm: CertificateAlgorithm,
+    key_pair: Option<rustls::PrivateKey>,
+    not_before: Option<SystemTime>,
+    not_after: Option<SystemTime>,
+    clock_skew: Duration,
+    serial_number: Option<Vec<u8>>,
+}
+
+impl CertificateParams {
+    /// Creates the default parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the algorithm of the generated certificate keypair.
+    ///
+    /// Ignored when a keypair is supplied through [`CertificateParams::with_key_pair`].
+    pub fn with_algorithm(mut self, algorithm: CertificateAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Signs the certificates with the given PKCS#8 DER-encoded keypair instead of generating a
+    /// new one. The keypair must use one of the [`CertificateAlgorithm`]s.
+    pub fn with_key_pair(mut self, key_pair: rustls::PrivateKey) -> Self {
+        self.key_pair = Some(key_pair);
+        self
+    }
+
+    /// Sets the notBefore and notAfter fields of the certificates.
+    pub fn with_validity(mut self, not_before: SystemTime, not_after: SystemTime) -> Self {
+        self.not_before = Some(not_before);
+        self.not_after = Some(not_after);
+        self
+    }
+
+    /// Widens the validity period by `clock_skew` on both ends, so that peers whose clocks are
+    /// off by up to that amount still accept the certificates.
+    pub fn with_clock_skew(mut self, clock_skew: Duration) -> Self {
+        self.clock_skew = clock_skew;
+        self
+    }
+
+    /// Sets the serial number of the certificates, as the big-endian bytes of a positive integer.
+    ///
+    /// RFC 5280 allows serial numbers of at most 20 bytes.
+    pub fn with_serial_number(mut self, serial_number: impl Into<Vec<u8>>) -> Self {
+        self.serial_number = Some(serial_number.into());
+        self
+    }
+}
 
 /// Generates a self-signed TLS certificate that includes a libp2p-specific
 /// certificate extension containing the public key of the given keypair.
 pub fn generate(
     identity_keypair: &identity::Keypair,
+) -> Result<(rustls::Certificate, rustls::PrivateKey), GenError> {
+    generate_with_params(identity_keypair, &CertificateParams::default())
+}
+
+/// Like [`generate`], but the certificate keypair and validity period are taken from `params`.
+pub fn generate_with_params(
+    identity_keypair: &identity::Keypair,
+    params: &CertificateParams,
 ) -> Result<(rustls::Certificate, rustls::PrivateKey), GenError> {
     // Keypair used to sign the certificate.
     // SHOULD NOT be related to the host's key.
     // Endpoints MAY generate a new key and certificate
     // for every connection attempt, or they MAY reuse the same key
     // and certificate for multiple connections.
-    let certificate_keypair = rcgen::KeyPair::generate(P2P_SIGNATURE_ALGORITHM)?;
+    let certificate_keypair = match &params.key_pair {
+        Some(key_pair) => {
+            let key_pair = rcgen::KeyPair::from_der(&key_pair.0)?;
+            if CertificateAlgorithm::from_signature_algorithm(key_pair.algorithm()).is_none() {
+                return Err(rcgen::RcgenError::UnsupportedSignatureAlgorithm.into());
+            }
+            key_pair
+        }
+        None => rcgen::KeyPair::generate(params.algorithm.signature_algorithm())?,
+    };
     let rustls_key = rustls::PrivateKey(certificate_keypair.serialize_der());
 
     let certificate = {
-        let mut params = rcgen::CertificateParams::new(vec![]);
-        params.distinguished_name = rcgen::DistinguishedName::new();
-        params.custom_extensions.push(make_libp2p_extension(
+        let mut rcgen_params = rcgen::CertificateParams::new(vec![]);
+        rcgen_params.distinguished_name = rcgen::DistinguishedName::new();
+        rcgen_params.custom_extensions.push(make_libp2p_extension(
             identity_keypair,
             &certificate_keypair,
         )?);
-        params.alg = P2P_SIGNATURE_ALGORITHM;
-        params.key_pair = Some(certificate_keypair);
-        rcgen::Certificate::from_params(params)?
+        if let Some(not_before) = params.not_before {
+            rcgen_params.not_before = to_offset_date_time(not_before)?;
+        }
+        if let Some(not_after) = params.not_after {
+            rcgen_params.not_after = to_offset_date_time(not_after)?;
+        }
+        let clock_skew =
+            ::time::Duration::try_from(params.clock_skew).map_err(|_| rcgen::RcgenError::Time)?;
+        rcgen_params.not_before =
+            to_certificate_time(rcgen_params.not_before.checked_sub(clock_skew))?;
+        rcgen_params.not_after =
+            to_certificate_time(rcgen_params.not_after.checked_add(clock_skew))?;
+        if rcgen_params.not_after < rcgen_params.not_before {
+            return Err(rcgen::RcgenError::Time.into());
+        }
+        if let Some(serial_number) = &params.serial_number {
+            rcgen_params.serial_number = Some(serial_number.clone().into());
+        }
+        rcgen_params.alg = certificate_keypair.algorithm();
+        rcgen_params.key_pair = Some(certificate_keypair);
+        rcgen::Certificate::from_params(rcgen_params)?
     };
 
     let rustls_certificate = rustls::Certificate(certificate.serialize_der()?);
@@ -52,18 +177,128 @@ pub fn generate(
     Ok((rustls_certificate, rustls_key))
 }
 
+/// Converts `at` for rcgen, failing if it lies outside the range certificates can express.
+fn to_offset_date_time(at: SystemTime) -> Result<::time::OffsetDateTime, GenError> {
+    let at = match at.duration_since(UNIX_EPOCH) {
+        Ok(since) => ::time::Duration::try_from(since)
+            .ok()
+            .and_then(|since| ::time::OffsetDateTime::UNIX_EPOCH.checked_add(since)),
+        Err(e) => ::time::Duration::try_from(e.duration())
+            .ok()
+            .and_then(|before| ::time::OffsetDateTime::UNIX_EPOCH.checked_sub(before)),
+    };
+
+    to_certificate_time(at)
+}
+
+/// Fails unless `at` is set and falls within the years 0 to 9999, which is all that X.509
+/// validity periods can express.
+fn to_certificate_time(
+    at: Option<::time::OffsetDateTime>,
+) -> Result<::time::OffsetDateTime, GenError> {
+    at.filter(|at| (0..10_000).contains(&at.year()))
+        .ok_or_else(|| rcgen::RcgenError::Time.into())
+}
+
 /// Attempts to parse the provided bytes as a [`P2pCertificate`].
 ///
 /// For this to succeed, the certificate must contain the specified extension and the signature must
 /// match the embedded public key.
 pub fn parse(certificate: &rustls::Certificate) -> Result<P2pCertificate<'_>, ParseError> {
+    parse_at(certificate, SystemTime::now(), Duration::ZERO)
+}
+
+/// Like [`parse`], but checks the validity period of the certificate at `now`, tolerating clocks
+/// that are off by up to `clock_skew`.
+pub fn parse_at(
+    certificate: &rustls::Certificate,
+    now: SystemTime,
+    clock_skew: Duration,
+) -> Result<P2pCertificate<'_>, ParseError> {
     let certificate = parse_unverified(certificate.as_ref())?;
 
-    certificate.verify()?;
+    certificate.verify_at(now, clock_skew)?;
 
     Ok(certificate)
 }
 
+/// Parses the provided bytes and reports what they contain, without rejecting the certificate
+/// when its validity period or any of its signatures fail to verify.
+///
+/// Only certificates that cannot be parsed at all, e.g. because the libp2p extension is missing,
+/// duplicated or malformed, produce an error.
+pub fn inspect(certificate: &rustls::Certificate) -> Result<CertificateInfo, ParseError> {
+    let certificate = parse_unverified(certificate.as_ref())?;
+
+    Ok(certificate.inspect())
+}
+
+/// The contents of a libp2p TLS certificate, as reported by [`inspect`].
+#[derive(Debug, Clone)]
+pub struct CertificateInfo {
+    public_key: identity::PublicKey,
+    signature_scheme: Option<rustls::SignatureScheme>,
+    not_before: SystemTime,
+    not_after: SystemTime,
+    serial: Vec<u8>,
+    extension: Vec<u8>,
+    self_signature_valid: bool,
+    host_signature_valid: bool,
+}
+
+impl CertificateInfo {
+    /// The public host key carried in the libp2p extension.
+    pub fn public_key(&self) -> &identity::PublicKey {
+        &self.public_key
+    }
+
+    /// The type of the public host key.
+    pub fn key_type(&self) -> identity::KeyType {
+        self.public_key.key_type()
+    }
+
+    /// The [`PeerId`] derived from the public host key.
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.to_peer_id()
+    }
+
+    /// The signature scheme of the certificate key, or `None` if it is not allowed by libp2p.
+    pub fn signature_scheme(&self) -> Option<rustls::SignatureScheme> {
+        self.signature_scheme
+    }
+
+    /// The start of the validity period (notBefore).
+    pub fn not_before(&self) -> SystemTime {
+        self.not_before
+    }
+
+    /// The end of the validity period (notAfter).
+    pub fn not_after(&self) -> SystemTime {
+        self.not_after
+    }
+
+    /// The raw bytes of the serial number.
+    pub fn serial(&self) -> &[u8] {
+        &self.serial
+    }
+
+    /// The raw DER-encoded value of the libp2p Public Key Extension.
+    pub fn extension(&self) -> &[u8] {
+        &self.extension
+    }
+
+    /// Whether the certificate is self-signed with a supported signature scheme.
+    pub fn self_signature_valid(&self) -> bool {
+        self.self_signature_valid
+    }
+
+    /// Whether the libp2p extension holds a valid signature of the certificate key
+    /// by the host key.
+    pub fn host_signature_valid(&self) -> bool {
+        self.host_signature_valid
+    }
+}
+
 /// An X.509 certificate with a libp2p-specific extension
 /// is used to secure libp2p connections.
 #[derive(Debug)]
@@ -100,7 +335,7 @@ pub struct VerificationError(#[from] pub(crate) webpki::Error);
 /// Internal function that only parses but does not verify the certificate.
 ///
 /// Useful for testing but unsuitable for production.
-fn parse_unverified(der_input: &[u8]) -> Result<P2pCertificate, webpki::Error> {
+fn parse_unverified(der_input: &[u8]) -> Result<P2pCertificate<'_>, webpki::Error> {
     let x509 = X509Certificate::from_der(der_input)
         .map(|(_rest_input, x509)| x509)
         .map_err(|_| webpki::Error::BadDer)?;
@@ -170,11 +405,21 @@ fn parse_unverified(der_input: &[u8]) -> Result<P2pCertificate, webpki::Error> {
     Ok(certificate)
 }
 
+fn to_system_time(time: ASN1Time) -> SystemTime {
+    let timestamp = time.timestamp();
+    let offset = Duration::from_secs(timestamp.unsigned_abs());
+    if timestamp < 0 {
+        UNIX_EPOCH - offset
+    } else {
+        UNIX_EPOCH + offset
+    }
+}
+
 fn make_libp2p_extension(
     identity_keypair: &identity::Keypair,
     certificate_keypair: &rcgen::KeyPair,
 ) -> Result<rcgen::CustomExtension, rcgen::RcgenError> {
-    // The peer signs the concatenation of the string `p2p-tls-handshake:`
+    // The peer signs the concatenation of the string `libp2p-tls-handshake:`
     // and the public key that it used to generate the certificate carrying
     // the libp2p Public Key Extension, using its private host key.
     let signature = {
@@ -206,12 +451,143 @@ fn make_libp2p_extension(
     Ok(ext)
 }
 
+/// A parsed and verified certificate.
+///
+/// Unlike [`P2pCertificate`], it does not borrow the DER encoding, so it can be kept around for
+/// the rest of the handshake.
+#[derive(Debug)]
+pub(crate) struct VerifiedCertificate {
+    public_key: identity::PublicKey,
+    signature_scheme: rustls::SignatureScheme,
+    subject_public_key: Vec<u8>,
+    not_before: SystemTime,
+    not_after: SystemTime,
+}
+
+impl VerifiedCertificate {
+    /// The [`PeerId`] of the remote peer.
+    pub(crate) fn peer_id(&self) -> PeerId {
+        self.public_key.to_peer_id()
+    }
+
+    /// The public host key of the remote peer.
+    pub(crate) fn public_key(&self) -> &identity::PublicKey {
+        &self.public_key
+    }
+
+    /// Whether the certificate is valid at `now`, tolerating clocks that are off by up to
+    /// `clock_skew`.
+    pub(crate) fn is_valid_at(&self, now: SystemTime, clock_skew: Duration) -> bool {
+        is_within_validity(self.not_before, self.not_after, now, clock_skew)
+    }
+
+    /// Verify the `signature` of the `message` signed by the private key corresponding to the
+    /// public key stored in the certificate.
+    pub(crate) fn verify_signature(
+        &self,
+        signature_scheme: rustls::SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<(), VerificationError> {
+        verify_signature(
+            self.signature_scheme,
+            &self.subject_public_key,
+            signature_scheme,
+            message,
+            signature,
+        )
+    }
+}
+
+/// Parses and verifies the certificate at `now`, keeping only what the handshake needs.
+pub(crate) fn parse_verified(
+    certificate: &rustls::Certificate,
+    now: SystemTime,
+    clock_skew: Duration,
+) -> Result<VerifiedCertificate, ParseError> {
+    Ok(parse_at(certificate, now, clock_skew)?.into_verified()?)
+}
+
+/// Whether `now` lies between `not_before` and `not_after`, widened by `clock_skew` on both ends.
+fn is_within_validity(
+    not_before: SystemTime,
+    not_after: SystemTime,
+    now: SystemTime,
+    clock_skew: Duration,
+) -> bool {
+    let not_yet_valid = now
+        .checked_add(clock_skew)
+        .is_some_and(|latest| latest < not_before);
+    let expired = now
+        .checked_sub(clock_skew)
+        .is_some_and(|earliest| earliest > not_after);
+
+    !not_yet_valid && !expired
+}
+
+/// Verify the `signature` of the `message` with the `subject_public_key` of a certificate, whose
+/// own signature scheme is `certificate_signature_scheme`.
+///
+/// Return `Error` if the `signature_scheme` does not match the public key signature
+/// and hashing algorithm or if the `signature_scheme` is not supported.
+fn verify_signature(
+    certificate_signature_scheme: rustls::SignatureScheme,
+    subject_public_key: &[u8],
+    signature_scheme: rustls::SignatureScheme,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), VerificationError> {
+    use ring::signature;
+    use rustls::SignatureScheme::*;
+
+    if signature_scheme != certificate_signature_scheme {
+        // This certificate was signed with a different signature scheme
+        return Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey.into());
+    }
+
+    let verification_algorithm: &dyn signature::VerificationAlgorithm = match signature_scheme {
+        RSA_PKCS1_SHA256 => &signature::RSA_PKCS1_2048_8192_SHA256,
+        RSA_PKCS1_SHA384 => &signature::RSA_PKCS1_2048_8192_SHA384,
+        RSA_PKCS1_SHA512 => &signature::RSA_PKCS1_2048_8192_SHA512,
+        ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+        ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+        ECDSA_NISTP521_SHA512 => {
+            // See https://github.com/briansmith/ring/issues/824
+            return Err(webpki::Error::UnsupportedSignatureAlgorithm.into());
+        }
+        RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
+        RSA_PSS_SHA384 => &signature::RSA_PSS_2048_8192_SHA384,
+        RSA_PSS_SHA512 => &signature::RSA_PSS_2048_8192_SHA512,
+        ED25519 => &signature::ED25519,
+        ED448 => {
+            // See https://github.com/briansmith/ring/issues/463
+            return Err(webpki::Error::UnsupportedSignatureAlgorithm.into());
+        }
+        // Similarly, hash functions with an output length less than 256 bits
+        // MUST NOT be used, due to the possibility of collision attacks.
+        // In particular, MD5 and SHA1 MUST NOT be used.
+        RSA_PKCS1_SHA1 => return Err(webpki::Error::UnsupportedSignatureAlgorithm.into()),
+        ECDSA_SHA1_Legacy => return Err(webpki::Error::UnsupportedSignatureAlgorithm.into()),
+        _ => return Err(webpki::Error::UnsupportedSignatureAlgorithm.into()),
+    };
+    signature::UnparsedPublicKey::new(verification_algorithm, subject_public_key)
+        .verify(message, signature)
+        .map_err(|_| webpki::Error::InvalidSignatureForPublicKey)?;
+
+    Ok(())
+}
+
 impl P2pCertificate<'_> {
     /// The [`PeerId`] of the remote peer.
     pub fn peer_id(&self) -> PeerId {
         self.extension.public_key.to_peer_id()
     }
 
+    /// The DER-encoded public key of the certificate keypair.
+    pub(crate) fn subject_public_key(&self) -> &[u8] {
+        self.certificate.public_key().raw
+    }
+
     /// Verify the `signature` of the `message` signed by the private key corresponding to the public key stored
     /// in the certificate.
     pub fn verify_signature(
@@ -220,61 +596,37 @@ impl P2pCertificate<'_> {
         message: &[u8],
         signature: &[u8],
     ) -> Result<(), VerificationError> {
-        let pk = self.public_key(signature_scheme)?;
-        pk.verify(message, signature)
-            .map_err(|_| webpki::Error::InvalidSignatureForPublicKey)?;
-
-        Ok(())
-    }
-
-    /// Get a [`ring::signature::UnparsedPublicKey`] for this `signature_scheme`.
-    /// Return `Error` if the `signature_scheme` does not match the public key signature
-    /// and hashing algorithm or if the `signature_scheme` is not supported.
-    fn public_key(
-        &self,
-        signature_scheme: rustls::SignatureScheme,
-    ) -> Result<ring::signature::UnparsedPublicKey<&[u8]>, webpki::Error> {
-        use ring::signature;
-        use rustls::SignatureScheme::*;
-
         let current_signature_scheme = self.signature_scheme()?;
-        if signature_scheme != current_signature_scheme {
-            // This certificate was signed with a different signature scheme
-            return Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey);
-        }
-
-        let verification_algorithm: &dyn signature::VerificationAlgorithm = match signature_scheme {
-            RSA_PKCS1_SHA256 => &signature::RSA_PKCS1_2048_8192_SHA256,
-            RSA_PKCS1_SHA384 => &signature::RSA_PKCS1_2048_8192_SHA384,
-            RSA_PKCS1_SHA512 => &signature::RSA_PKCS1_2048_8192_SHA512,
-            ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
-            ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
-            ECDSA_NISTP521_SHA512 => {
-                // See https://github.com/briansmith/ring/issues/824
-                return Err(webpki::Error::UnsupportedSignatureAlgorithm);
-            }
-            RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
-            RSA_PSS_SHA384 => &signature::RSA_PSS_2048_8192_SHA384,
-            RSA_PSS_SHA512 => &signature::RSA_PSS_2048_8192_SHA512,
-            ED25519 => &signature::ED25519,
-            ED448 => {
-                // See https://github.com/briansmith/ring/issues/463
-                return Err(webpki::Error::UnsupportedSignatureAlgorithm);
-            }
-            // Similarly, hash functions with an output length less than 256 bits
-            // MUST NOT be used, due to the possibility of collision attacks.
-            // In particular, MD5 and SHA1 MUST NOT be used.
-            RSA_PKCS1_SHA1 => return Err(webpki::Error::UnsupportedSignatureAlgorithm),
-            ECDSA_SHA1_Legacy => return Err(webpki::Error::UnsupportedSignatureAlgorithm),
-            _ => return Err(webpki::Error::UnsupportedSignatureAlgorithm),
-        };
         let spki = &self.certificate.tbs_certificate.subject_pki;
-        let key = signature::UnparsedPublicKey::new(
-            verification_algorithm,
+
+        verify_signature(
+            current_signature_scheme,
             spki.subject_public_key.as_ref(),
-        );
+            signature_scheme,
+            message,
+            signature,
+        )
+    }
+
+    /// Verifies the certificate and keeps what the rest of the handshake needs, so that the
+    /// certificate does not have to be parsed again.
+    pub(crate) fn into_verified(self) -> Result<VerifiedCertificate, webpki::Error> {
+        let signature_scheme = self.signature_scheme()?;
+        let validity = self.certificate.validity();
 
-        Ok(key)
+        Ok(VerifiedCertificate {
+            public_key: self.extension.public_key.clone(),
+            signature_scheme,
+            subject_public_key: self
+                .certificate
+                .tbs_certificate
+                .subject_pki
+                .subject_public_key
+                .as_ref()
+                .to_vec(),
+            not_before: to_system_time(validity.not_before),
+            not_after: to_system_time(validity.not_after),
+        })
     }
 
     /// This method validates the certificate according to libp2p TLS 1.3 specs.
@@ -284,14 +636,28 @@ impl P2pCertificate<'_> {
     /// 3. use hash functions with an output length not less than 256 bits;
     /// 4. be self signed;
     /// 5. contain a valid signature in the specific libp2p extension.
-    fn verify(&self) -> Result<(), webpki::Error> {
+    ///
+    /// The validity period is checked at `now`, tolerating clocks that are off by up to
+    /// `clock_skew`.
+    fn verify_at(&self, now: SystemTime, clock_skew: Duration) -> Result<(), webpki::Error> {
         use webpki::Error;
         // The certificate MUST have NotBefore and NotAfter fields set
         // such that the certificate is valid at the time it is received by the peer.
-        if !self.certificate.validity().is_valid() {
+        let validity = self.certificate.validity();
+        let not_before = to_system_time(validity.not_before);
+        let not_after = to_system_time(validity.not_after);
+        if !is_within_validity(not_before, not_after, now, clock_skew) {
             return Err(Error::InvalidCertValidity);
         }
 
+        self.verify_self_signature()?;
+        self.verify_host_signature()?;
+
+        Ok(())
+    }
+
+    /// Verifies that the certificate is self-signed with a supported signature scheme.
+    fn verify_self_signature(&self) -> Result<(), webpki::Error> {
         // Certificates MUST use the NamedCurve encoding for elliptic curve parameters.
         // Similarly, hash functions with an output length less than 256 bits
         // MUST NOT be used, due to the possibility of collision attacks.
@@ -304,11 +670,14 @@ impl P2pCertificate<'_> {
         let signature = self.certificate.signature_value.as_ref();
         // check if self signed
         self.verify_signature(signature_scheme, raw_certificate, signature)
-            .map_err(|_| Error::SignatureAlgorithmMismatch)?;
+            .map_err(|_| webpki::Error::SignatureAlgorithmMismatch)
+    }
 
+    /// Verifies the signature of the certificate key by the host key in the libp2p extension.
+    fn verify_host_signature(&self) -> Result<(), webpki::Error> {
         let subject_pki = self.certificate.public_key().raw;
 
-        // The peer signs the concatenation of the string `p2p-tls-handshake:`
+        // The peer signs the concatenation of the string `libp2p-tls-handshake:`
         // and the public key that it used to generate the certificate carrying
         // the libp2p Public Key Extension, using its private host key.
         let mut msg = vec![];
@@ -324,12 +693,37 @@ impl P2pCertificate<'_> {
             .public_key
             .verify(&msg, &self.extension.signature);
         if !user_owns_sk {
-            return Err(Error::UnknownIssuer);
+            return Err(webpki::Error::UnknownIssuer);
         }
 
         Ok(())
     }
 
+    /// Reports the contents of the certificate and the outcome of each signature check.
+    fn inspect(&self) -> CertificateInfo {
+        let p2p_ext_oid =
+            der_parser::oid::Oid::from(P2P_EXT_OID).expect("This is a valid OID of p2p extension.");
+        let extension = self
+            .certificate
+            .extensions()
+            .iter()
+            .find(|ext| ext.oid == p2p_ext_oid)
+            .map(|ext| ext.value.to_vec())
+            .unwrap_or_default();
+        let validity = self.certificate.validity();
+
+        CertificateInfo {
+            public_key: self.extension.public_key.clone(),
+            signature_scheme: self.signature_scheme().ok(),
+            not_before: to_system_time(validity.not_before),
+            not_after: to_system_time(validity.not_after),
+            serial: self.certificate.tbs_certificate.raw_serial().to_vec(),
+            extension,
+            self_signature_valid: self.verify_self_signature().is_ok(),
+            host_signature_valid: self.verify_host_signature().is_ok(),
+        }
+    }
+
     /// Return the signature scheme corresponding to [`AlgorithmIdentifier`]s
     /// of `subject_pki` and `signature_algorithm`
     /// according to <https://www.rfc-editor.org/rfc/rfc8446.html#section-4.2.3>.
@@ -418,3 +812,334 @@ impl P2pCertificate<'_> {
         Err(webpki::Error::UnsupportedSignatureAlgorithm)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The `spec_*` certificates are the test vectors published in the libp2p TLS specification,
+    // see <https://github.com/libp2p/specs/blob/master/tls/tls.md#test-vectors>. The remaining
+    // ones are crafted with `rcgen` from the Ed25519 host key whose secret is `[7; 32]`.
+    macro_rules! certificate {
+        ($name:literal) => {
+            rustls::Certificate(
+                include_bytes!(concat!("../tests/data/certificates/", $name, ".der")).to_vec(),
+            )
+        };
+    }
+
+    const CRAFTED_PEER_ID: &str = "12D3KooWRawPbxPtP1eZaJpumGnyWX2DcUyd3RQnydr3eAto4Az7";
+
+    fn parse_error(certificate: &rustls::Certificate) -> webpki::Error {
+        let ParseError(e) = parse(certificate).expect_err("certificate to be rejected");
+        e
+    }
+
+    #[test]
+    fn sanity_check() {
+        let keypair = identity::Keypair::generate_ed25519();
+
+        let (certificate, _) = generate(&keypair).unwrap();
+        let parsed = parse(&certificate).unwrap();
+
+        assert_eq!(parsed.peer_id(), keypair.public().to_peer_id());
+    }
+
+    #[test]
+    fn spec_ed25519() {
+        let certificate = certificate!("spec_ed25519");
+
+        assert_eq!(
+            parse(&certificate).unwrap().peer_id(),
+            "12D3KooWJRSrypvnpHgc6ZAgyCni4KcSmbV7uGRaMw5LgMKT18fq"
+                .parse::<PeerId>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn spec_ecdsa() {
+        let certificate = certificate!("spec_ecdsa");
+
+        assert_eq!(
+            parse(&certificate).unwrap().peer_id(),
+            "QmZcrvr3r4S3QvwFdae3c2EWTfo792Y14UpzCZurhmiWeX"
+                .parse::<PeerId>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn spec_secp256k1() {
+        let certificate = certificate!("spec_secp256k1");
+
+        assert_eq!(
+            parse(&certificate).unwrap().peer_id(),
+            "16Uiu2HAm2dSCBFxuge46aEt7U1oejtYuBUZXxASHqmcfVmk4gsbx"
+                .parse::<PeerId>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn spec_invalid_signature() {
+        let certificate = certificate!("spec_invalid_signature");
+
+        assert_eq!(parse_error(&certificate), webpki::Error::UnknownIssuer);
+    }
+
+    #[test]
+    fn missing_extension() {
+        let certificate = certificate!("missing_extension");
+
+        assert_eq!(parse_error(&certificate), webpki::Error::BadDer);
+    }
+
+    #[test]
+    fn duplicate_extension() {
+        let certificate = certificate!("duplicate_extension");
+
+        assert_eq!(parse_error(&certificate), webpki::Error::BadDer);
+    }
+
+    #[test]
+    fn unsupported_critical_extension() {
+        let certificate = certificate!("unsupported_critical_extension");
+
+        assert_eq!(
+            parse_error(&certificate),
+            webpki::Error::UnsupportedCriticalExtension
+        );
+    }
+
+    #[test]
+    fn expired() {
+        let certificate = certificate!("expired");
+
+        assert_eq!(
+            parse_unverified(certificate.as_ref()).unwrap().peer_id(),
+            CRAFTED_PEER_ID.parse::<PeerId>().unwrap()
+        );
+        assert_eq!(
+            parse_error(&certificate),
+            webpki::Error::InvalidCertValidity
+        );
+    }
+
+    #[test]
+    fn not_yet_valid() {
+        let certificate = certificate!("not_yet_valid");
+
+        assert_eq!(
+            parse_unverified(certificate.as_ref()).unwrap().peer_id(),
+            CRAFTED_PEER_ID.parse::<PeerId>().unwrap()
+        );
+        assert_eq!(
+            parse_error(&certificate),
+            webpki::Error::InvalidCertValidity
+        );
+    }
+
+    #[test]
+    fn expired_within_clock_skew() {
+        let certificate = certificate!("expired");
+        let not_after = inspect(&certificate).unwrap().not_after();
+        let skew = Duration::from_secs(60);
+
+        assert!(parse_at(&certificate, not_after - skew, Duration::ZERO).is_ok());
+        assert!(parse_at(&certificate, not_after + skew, Duration::ZERO).is_err());
+        assert!(parse_at(&certificate, not_after + skew, skew).is_ok());
+        assert!(parse_at(&certificate, not_after + 2 * skew, skew).is_err());
+    }
+
+    #[test]
+    fn not_yet_valid_within_clock_skew() {
+        let certificate = certificate!("not_yet_valid");
+        let not_before = inspect(&certificate).unwrap().not_before();
+        let skew = Duration::from_secs(60);
+
+        assert!(parse_at(&certificate, not_before + skew, Duration::ZERO).is_ok());
+        assert!(parse_at(&certificate, not_before - skew, Duration::ZERO).is_err());
+        assert!(parse_at(&certificate, not_before - skew, skew).is_ok());
+        assert!(parse_at(&certificate, not_before - 2 * skew, skew).is_err());
+    }
+
+    #[test]
+    fn inspect_valid_certificate() {
+        let certificate = certificate!("spec_ecdsa");
+
+        let info = inspect(&certificate).unwrap();
+
+        assert_eq!(info.key_type(), identity::KeyType::Ecdsa);
+        assert_eq!(info.peer_id(), parse(&certificate).unwrap().peer_id());
+        assert_eq!(
+            info.signature_scheme(),
+            Some(rustls::SignatureScheme::ECDSA_NISTP256_SHA256)
+        );
+        assert!(info.not_before() < SystemTime::now());
+        assert!(info.not_after() > SystemTime::now());
+        assert!(!info.serial().is_empty());
+        assert!(!info.extension().is_empty());
+        assert!(info.self_signature_valid());
+        assert!(info.host_signature_valid());
+    }
+
+    #[test]
+    fn inspect_reports_invalid_host_signature() {
+        let info = inspect(&certificate!("spec_invalid_signature")).unwrap();
+
+        assert_eq!(info.key_type(), identity::KeyType::Ed25519);
+        assert!(info.self_signature_valid());
+        assert!(!info.host_signature_valid());
+    }
+
+    #[test]
+    fn inspect_reports_validity_period() {
+        let info = inspect(&certificate!("expired")).unwrap();
+
+        assert_eq!(info.peer_id(), CRAFTED_PEER_ID.parse::<PeerId>().unwrap());
+        assert_eq!(
+            info.not_after(),
+            UNIX_EPOCH + Duration::from_secs(946_684_800)
+        );
+        assert!(info.self_signature_valid());
+        assert!(info.host_signature_valid());
+    }
+
+    #[test]
+    fn inspect_rejects_missing_extension() {
+        let ParseError(e) = inspect(&certificate!("missing_extension")).unwrap_err();
+
+        assert_eq!(e, webpki::Error::BadDer);
+    }
+
+    #[test]
+    fn generate_with_every_algorithm() {
+        let keypair = identity::Keypair::generate_ed25519();
+
+        for (algorithm, scheme) in [
+            (
+                CertificateAlgorithm::EcdsaP256Sha256,
+                rustls::SignatureScheme::ECDSA_NISTP256_SHA256,
+            ),
+            (
+                CertificateAlgorithm::EcdsaP384Sha384,
+                rustls::SignatureScheme::ECDSA_NISTP384_SHA384,
+            ),
+            (
+                CertificateAlgorithm::Ed25519,
+                rustls::SignatureScheme::ED25519,
+            ),
+        ] {
+            let params = CertificateParams::new().with_algorithm(algorithm);
+            let (certificate, _) = generate_with_params(&keypair, &params).unwrap();
+
+            let parsed = parse(&certificate).unwrap();
+            assert_eq!(parsed.signature_scheme(), Ok(scheme));
+            assert_eq!(parsed.peer_id(), keypair.public().to_peer_id());
+        }
+    }
+
+    #[test]
+    fn generate_with_supplied_key_pair() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let certificate_keypair = rcgen::KeyPair::generate(&rcgen::PKCS_ED25519).unwrap();
+        let private_key = rustls::PrivateKey(certificate_keypair.serialize_der());
+
+        let params = CertificateParams::new()
+            .with_algorithm(CertificateAlgorithm::EcdsaP384Sha384)
+            .with_key_pair(private_key.clone());
+        let (certificate, key) = generate_with_params(&keypair, &params).unwrap();
+
+        assert_eq!(key, private_key);
+        let parsed = parse(&certificate).unwrap();
+        assert_eq!(
+            parsed.certificate.public_key().raw,
+            certificate_keypair.public_key_der()
+        );
+        assert_eq!(
+            parsed.signature_scheme(),
+            Ok(rustls::SignatureScheme::ED25519)
+        );
+    }
+
+    #[test]
+    fn generate_rejects_unsupported_key_pair() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let rsa_key = rustls::PrivateKey(include_bytes!("../tests/data/rsa-2048.pk8").to_vec());
+
+        let params = CertificateParams::new().with_key_pair(rsa_key);
+
+        assert!(matches!(
+            generate_with_params(&keypair, &params),
+            Err(GenError(rcgen::RcgenError::UnsupportedSignatureAlgorithm))
+        ));
+    }
+
+    #[test]
+    fn generate_with_validity_and_clock_skew() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let not_before = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let not_after = not_before + Duration::from_secs(24 * 60 * 60);
+        let clock_skew = Duration::from_secs(5 * 60);
+
+        let params = CertificateParams::new()
+            .with_validity(not_before, not_after)
+            .with_clock_skew(clock_skew);
+        let (certificate, _) = generate_with_params(&keypair, &params).unwrap();
+
+        let info = inspect(&certificate).unwrap();
+        assert_eq!(info.not_before(), not_before - clock_skew);
+        assert_eq!(info.not_after(), not_after + clock_skew);
+    }
+
+    #[test]
+    fn generate_rejects_out_of_range_validity() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let not_before = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let not_after = not_before + Duration::from_secs(24 * 60 * 60);
+
+        for params in [
+            CertificateParams::new().with_clock_skew(Duration::MAX),
+            CertificateParams::new()
+                .with_validity(not_before, not_after)
+                .with_clock_skew(Duration::from_secs(10_000 * 365 * 24 * 60 * 60)),
+            CertificateParams::new().with_validity(
+                not_before,
+                not_after + Duration::from_secs(10_000 * 365 * 24 * 60 * 60),
+            ),
+            CertificateParams::new().with_validity(
+                UNIX_EPOCH - Duration::from_secs(2_000 * 365 * 24 * 60 * 60),
+                not_after,
+            ),
+        ] {
+            assert!(matches!(
+                generate_with_params(&keypair, &params),
+                Err(GenError(rcgen::RcgenError::Time))
+            ));
+        }
+    }
+
+    #[test]
+    fn generate_with_serial_number() {
+        let keypair = identity::Keypair::generate_ed25519();
+
+        let params = CertificateParams::new().with_serial_number([0x01, 0x23, 0x45, 0x67]);
+        let (certificate, _) = generate_with_params(&keypair, &params).unwrap();
+
+        assert_eq!(
+            inspect(&certificate).unwrap().serial(),
+            [0x01, 0x23, 0x45, 0x67]
+        );
+    }
+
+    #[test]
+    fn generate_rejects_inverted_validity() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let now = SystemTime::now();
+
+        let params = CertificateParams::new().with_validity(now, now - Duration::from_secs(60));
+
+        assert!(generate_with_params(&keypair, &params).is_err());
+    }
+}
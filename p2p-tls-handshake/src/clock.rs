@@ -0,0 +1,21 @@
+//! Time source for certificate validity checks
+
+use std::time::SystemTime;
+
+/// Tells the current time to the certificate verifier.
+///
+/// Without a clock, the verifier checks the validity period of peer certificates at the time
+/// reported by `rustls`, which is the system time.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> SystemTime;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> SystemTime + Send + Sync,
+{
+    fn now(&self) -> SystemTime {
+        self()
+    }
+}
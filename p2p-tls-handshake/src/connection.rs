@@ -0,0 +1,333 @@
+//! Connections secured by the TLS handshake
+//!
+//! A [`SecuredConnection`] is the output of the security upgrade. It reads and writes like the
+//! underlying [`TlsStream`], and additionally carries what the handshake established about the
+//! remote peer and the session, so that it is available without digging through the TLS state.
+//!
+//! It also exports keying material from the TLS session (RFC 8446 §7.5), so that the application
+//! can bind its own secrets, such as authentication tokens, to this one connection.
+
+use crate::certificate::VerifiedCertificate;
+use crate::error::TlsUpgradeError;
+use crate::P2P_ALPN;
+use futures::{AsyncRead, AsyncWrite};
+use futures_rustls::TlsStream;
+use libp2p_identity::{KeyType, PeerId, PublicKey};
+use pin_project::pin_project;
+use rustls::{CipherSuite, CommonState, ProtocolVersion};
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The label of the keying material exported by [`SecuredConnection::export_keying_material`].
+const EXPORTER_LABEL: &[u8] = b"EXPORTER-libp2p-tls";
+/// The label of the keying material exported by [`SecuredConnection::channel_binding`].
+const CHANNEL_BINDING_LABEL: &[u8] = b"EXPORTER-libp2p-tls-channel-binding";
+
+/// The identity of the remote peer, as proven by its certificate.
+#[derive(Debug, Clone)]
+pub struct RemoteIdentity {
+    public_key: PublicKey,
+    certificate: rustls::Certificate,
+    fingerprint: [u8; 32],
+}
+
+impl RemoteIdentity {
+    pub(crate) fn new(verified: &VerifiedCertificate, certificate: rustls::Certificate) -> Self {
+        Self {
+            public_key: verified.public_key().clone(),
+            fingerprint: fingerprint(&certificate),
+            certificate,
+        }
+    }
+
+    /// The [`PeerId`] of the remote peer.
+    pub fn peer_id(&self) -> PeerId {
+        self.public_key.to_peer_id()
+    }
+
+    /// The public host key of the remote peer.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// The type of the public host key of the remote peer.
+    pub fn key_type(&self) -> KeyType {
+        self.public_key.key_type()
+    }
+
+    /// The certificate presented by the remote peer.
+    pub fn certificate(&self) -> &rustls::Certificate {
+        &self.certificate
+    }
+
+    /// The SHA-256 digest of the DER encoding of [`certificate`](Self::certificate).
+    pub fn fingerprint(&self) -> &[u8; 32] {
+        &self.fingerprint
+    }
+}
+
+/// The parameters negotiated for the TLS session.
+#[derive(Debug, Clone)]
+pub struct SessionParameters {
+    cipher_suite: CipherSuite,
+    alpn_protocol: Option<Vec<u8>>,
+    protocol_version: ProtocolVersion,
+    resumed: bool,
+}
+
+impl SessionParameters {
+    /// Reads the parameters of the session from `state`, failing if the handshake did not
+    /// negotiate them.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn new(state: &CommonState, resumed: bool) -> Result<Self, TlsUpgradeError> {
+        Ok(Self {
+            cipher_suite: state
+                .negotiated_cipher_suite()
+                .ok_or(TlsUpgradeError::IncompleteHandshake)?
+                .suite(),
+            alpn_protocol: state.alpn_protocol().map(<[u8]>::to_vec),
+            protocol_version: state
+                .protocol_version()
+                .ok_or(TlsUpgradeError::IncompleteHandshake)?,
+            resumed,
+        })
+    }
+
+    /// The negotiated cipher suite.
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.cipher_suite
+    }
+
+    /// The protocol negotiated by ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// The negotiated TLS version.
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Whether a previous session was resumed, instead of performing a full handshake.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+}
+
+/// A connection secured by the TLS handshake.
+#[pin_project]
+#[derive(Debug)]
+pub struct SecuredConnection<C> {
+    #[pin]
+    stream: TlsStream<C>,
+    local_certificate: rustls::Certificate,
+    remote: RemoteIdentity,
+    session: SessionParameters,
+}
+
+impl<C> SecuredConnection<C> {
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn new(
+        stream: TlsStream<C>,
+        local_certificate: rustls::Certificate,
+        remote: RemoteIdentity,
+        resumed: bool,
+    ) -> Result<Self, TlsUpgradeError> {
+        let session = SessionParameters::new(stream.get_ref().1, resumed)?;
+
+        Ok(Self {
+            stream,
+            local_certificate,
+            remote,
+            session,
+        })
+    }
+
+    /// The certificate presented to the remote peer.
+    ///
+    /// If the session was resumed, this is the certificate presented when it was established.
+    pub fn local_certificate(&self) -> &rustls::Certificate {
+        &self.local_certificate
+    }
+
+    /// The identity of the remote peer.
+    pub fn remote(&self) -> &RemoteIdentity {
+        &self.remote
+    }
+
+    /// The parameters negotiated for the TLS session.
+    pub fn session(&self) -> &SessionParameters {
+        &self.session
+    }
+
+    /// The stream multiplexer agreed on during the handshake, if any.
+    ///
+    /// See [`Config::with_early_muxers`](crate::Config::with_early_muxers).
+    pub fn early_muxer(&self) -> Option<&str> {
+        early_muxer(&self.session)
+    }
+
+    /// The underlying TLS stream.
+    pub fn get_ref(&self) -> &TlsStream<C> {
+        &self.stream
+    }
+
+    /// The underlying TLS stream.
+    pub fn get_mut(&mut self) -> &mut TlsStream<C> {
+        &mut self.stream
+    }
+
+    /// Returns the underlying TLS stream, dropping the metadata.
+    pub fn into_inner(self) -> TlsStream<C> {
+        self.stream
+    }
+
+    /// Fills `output` with keying material exported from the TLS session under the label
+    /// `EXPORTER-libp2p-tls` and the given `context`.
+    ///
+    /// Both peers of a connection export the same keying material for the same `context`, while
+    /// any other connection exports unrelated keying material.
+    pub fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), rustls::Error> {
+        self.export(output, EXPORTER_LABEL, context)
+    }
+
+    /// Returns a value that binds this connection to the certificates of both peers.
+    ///
+    /// It is keying material exported under the label `EXPORTER-libp2p-tls-channel-binding`, with
+    /// the SHA-256 fingerprint of the dialer's certificate followed by the one of the listener's
+    /// certificate as context. Both peers compute the same value.
+    pub fn channel_binding(&self) -> Result<[u8; 32], rustls::Error> {
+        let local = fingerprint(&self.local_certificate);
+        let remote = self.remote.fingerprint();
+        let (dialer, listener) = match &self.stream {
+            TlsStream::Client(_) => (&local, remote),
+            TlsStream::Server(_) => (remote, &local),
+        };
+
+        let mut binding = [0; 32];
+        self.export(
+            &mut binding,
+            CHANNEL_BINDING_LABEL,
+            Some(&[&dialer[..], &listener[..]].concat()),
+        )?;
+
+        Ok(binding)
+    }
+
+    fn export(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: Option<&[u8]>,
+    ) -> Result<(), rustls::Error> {
+        match &self.stream {
+            TlsStream::Client(stream) => stream
+                .get_ref()
+                .1
+                .export_keying_material(output, label, context),
+            TlsStream::Server(stream) => stream
+                .get_ref()
+                .1
+                .export_keying_material(output, label, context),
+        }
+        .map(|_| ())
+    }
+}
+
+/// The protocol agreed on by ALPN, unless it is `libp2p`.
+fn early_muxer(session: &SessionParameters) -> Option<&str> {
+    session
+        .alpn_protocol()
+        .filter(|protocol| *protocol != P2P_ALPN)
+        .and_then(|protocol| std::str::from_utf8(protocol).ok())
+}
+
+/// The SHA-256 digest of the DER encoding of `certificate`.
+pub(crate) fn fingerprint(certificate: &rustls::Certificate) -> [u8; 32] {
+    ring::digest::digest(&ring::digest::SHA256, certificate.as_ref())
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 digests are 32 bytes long.")
+}
+
+impl<C> AsyncRead for SecuredConnection<C>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().stream.poll_read(cx, buf)
+    }
+
+    fn poll_read_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &mut [io::IoSliceMut<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().stream.poll_read_vectored(cx, bufs)
+    }
+}
+
+impl<C> AsyncWrite for SecuredConnection<C>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().stream.poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().stream.poll_write_vectored(cx, bufs)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().stream.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().stream.poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate;
+    use libp2p_identity::Keypair;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn remote_identity_fingerprint() {
+        let keypair = Keypair::generate_ed25519();
+        let (certificate, _) = certificate::generate(&keypair).unwrap();
+        let verified =
+            certificate::parse_verified(&certificate, SystemTime::now(), Duration::ZERO).unwrap();
+
+        let remote = RemoteIdentity::new(&verified, certificate.clone());
+
+        assert_eq!(remote.peer_id(), keypair.public().to_peer_id());
+        assert_eq!(remote.certificate(), &certificate);
+        assert_eq!(
+            &remote.fingerprint()[..],
+            ring::digest::digest(&ring::digest::SHA256, &certificate.0).as_ref()
+        );
+    }
+}
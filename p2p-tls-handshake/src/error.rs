@@ -1,20 +1,134 @@
 use crate::certificate::{GenError, ParseError};
-use libp2p_identity::PeerId;
+use crate::protobuf::DecodeError;
+use libp2p_identity::{DecodingError, PeerId, SigningError};
 use multistream_select::NegotiationError;
 use std::fmt;
+use std::time::{Duration, SystemTime};
 
 #[derive(thiserror::Error, Debug)]
 pub enum TlsUpgradeError {
     #[error("Failed to generate certificate")]
     CertificateGeneration(#[from] GenError),
     #[error("Failed to upgrade server connection")]
-    ServerUpgrade(std::io::Error),
+    ServerUpgrade(#[source] std::io::Error),
     #[error("Failed to upgrade client connection")]
-    ClientUpgrade(std::io::Error),
+    ClientUpgrade(#[source] std::io::Error),
     #[error("Failed to parse certificate")]
     BadCertificate(#[from] ParseError),
     #[error("Invalid peer ID (expected {expected:?}, found {found:?})")]
     PeerIdMismatch { expected: PeerId, found: PeerId },
+    #[error("Expected exactly one peer certificate, found {0}")]
+    CertificateCount(usize),
+    #[error("No local certificate was presented or resumed")]
+    MissingLocalCertificate,
+    #[error("TLS 1.2 signatures are not supported")]
+    UnsupportedTls12Signature,
+    #[error("Handshake completed without negotiating a cipher suite and protocol version")]
+    IncompleteHandshake,
+}
+
+/// Error that can happen during the Noise handshake.
+#[derive(thiserror::Error, Debug)]
+pub enum NoiseUpgradeError {
+    #[error("Failed to read or write handshake message")]
+    Io(#[from] std::io::Error),
+    #[error("Noise protocol error")]
+    Noise(#[from] snow::Error),
+    #[error("Failed to sign the static key")]
+    Signing(#[from] SigningError),
+    #[error("Invalid handshake payload")]
+    InvalidPayload(#[from] DecodeError),
+    #[error("Invalid identity key")]
+    InvalidIdentityKey(#[from] DecodingError),
+    #[error("Invalid signature of the static key")]
+    BadSignature,
+    #[error("Invalid peer ID (expected {expected:?}, found {found:?})")]
+    PeerIdMismatch { expected: PeerId, found: PeerId },
+}
+
+/// Error that can happen during the handshake of a [`SecurityAdapter`](crate::SecurityAdapter).
+#[derive(thiserror::Error, Debug)]
+pub enum SecurityAdapterError<E> {
+    #[error(transparent)]
+    Upgrade(E),
+    #[error("Invalid peer ID (expected {expected:?}, found {found:?})")]
+    PeerIdMismatch { expected: PeerId, found: PeerId },
+}
+
+/// Error that can happen during the plaintext handshake.
+#[cfg(feature = "plaintext")]
+#[derive(thiserror::Error, Debug)]
+pub enum PlaintextUpgradeError {
+    #[error("Failed to read or write exchange message")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid exchange message")]
+    InvalidPayload(#[from] DecodeError),
+    #[error("Invalid public key")]
+    InvalidPublicKey(#[from] DecodingError),
+    #[error("Invalid peer ID")]
+    InvalidPeerId(#[from] libp2p_identity::ParseError),
+    #[error("Peer ID {0} does not belong to the public key")]
+    PublicKeyMismatch(PeerId),
+    #[error("Invalid peer ID (expected {expected:?}, found {found:?})")]
+    PeerIdMismatch { expected: PeerId, found: PeerId },
+}
+
+/// Error that can happen when storing or loading a certificate.
+#[derive(thiserror::Error, Debug)]
+pub enum StoreError {
+    #[error("Failed to read or write certificate file")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to decode PEM")]
+    Pem(#[from] pem::PemError),
+    #[error("Missing {0} PEM block")]
+    MissingPemBlock(&'static str),
+    #[error("Unexpected {0} PEM block")]
+    UnexpectedPemBlock(String),
+    #[error("Failed to parse certificate")]
+    BadCertificate(#[from] ParseError),
+    #[error("Failed to parse private key")]
+    BadPrivateKey(#[source] GenError),
+    #[error("Private key does not belong to the certificate")]
+    PrivateKeyMismatch,
+    #[error("Certificate belongs to another identity ({0:?})")]
+    IdentityMismatch(PeerId),
+}
+
+/// Error that can happen when generating certificates for a
+/// [`RotatingCertResolver`](crate::RotatingCertResolver).
+#[derive(thiserror::Error, Debug)]
+pub enum RotationError {
+    #[error("Failed to generate certificate")]
+    Generation(#[from] GenError),
+    #[error("Failed to parse generated certificate")]
+    BadCertificate(#[from] ParseError),
+    #[error("Unsupported private key")]
+    BadPrivateKey(#[from] rustls::sign::SignError),
+    #[error("Certificate validity does not fit into the system time")]
+    ValidityOutOfRange,
+    #[error("Rotation interval is shorter than {0:?}")]
+    IntervalTooShort(Duration),
+    #[error("Certificate is due for renewal less than {0:?} after it is generated")]
+    RenewalTooSoon(Duration),
+    #[error("Certificate expired at {0:?}")]
+    Expired(SystemTime),
+}
+
+/// Error that can happen when loading a peer allowlist.
+#[derive(thiserror::Error, Debug)]
+pub enum AllowlistError {
+    #[error("Failed to read allowlist file")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid peer ID on line {line}")]
+    InvalidPeerId {
+        line: usize,
+        #[source]
+        source: libp2p_identity::ParseError,
+    },
+    #[error("Allowlist file is empty")]
+    Empty,
+    #[error("Allowlist file changed while being read")]
+    Changed,
 }
 
 /// Error that can happen when upgrading a connection or substream to use a protocol.
@@ -24,6 +138,8 @@ pub enum UpgradeError<E> {
     Select(NegotiationError),
     /// Error during the post-negotiation handshake.
     Apply(E),
+    /// The upgrade was polled again after it completed.
+    PolledAfterCompletion,
 }
 
 impl<E> UpgradeError<E> {
@@ -34,6 +150,7 @@ impl<E> UpgradeError<E> {
         match self {
             UpgradeError::Select(e) => UpgradeError::Select(e),
             UpgradeError::Apply(e) => UpgradeError::Apply(f(e)),
+            UpgradeError::PolledAfterCompletion => UpgradeError::PolledAfterCompletion,
         }
     }
 
@@ -53,6 +170,7 @@ where
         match self {
             UpgradeError::Select(_) => write!(f, "Multistream select failed"),
             UpgradeError::Apply(_) => write!(f, "Handshake failed"),
+            UpgradeError::PolledAfterCompletion => write!(f, "Upgrade polled after completion"),
         }
     }
 }
@@ -65,6 +183,7 @@ where
         match self {
             UpgradeError::Select(e) => Some(e),
             UpgradeError::Apply(e) => Some(e),
+            UpgradeError::PolledAfterCompletion => None,
         }
     }
 }
@@ -1,27 +1,65 @@
+mod allowlist;
 mod apply;
 mod boxed;
 mod certificate;
+mod clock;
+mod connection;
 mod error;
+mod noise;
+#[cfg(feature = "plaintext")]
+mod plaintext;
+mod policy;
+mod protobuf;
+mod resumption;
+mod rotation;
 mod secure;
-mod transport;
-mod upgrade;
+mod select;
+mod store;
+#[cfg(test)]
+mod test_utils;
+pub mod transport;
+pub mod upgrade;
 mod verifier;
 
-use certificate::GenError;
 use libp2p_identity::Keypair;
 use libp2p_identity::PeerId;
 use rustls::ClientConfig;
+use rustls::ConfigBuilder;
 use rustls::ServerConfig;
 use std::sync::Arc;
 use verifier::Libp2pCertificateVerifier;
 
-pub(crate) use apply::{
-    apply, apply_inbound, apply_outbound, InboundUpgradeApply, OutboundUpgradeApply,
+pub use allowlist::FileAllowlist;
+pub(crate) use apply::apply;
+pub use boxed::Boxed;
+pub use certificate::{
+    generate, generate_with_params, inspect, CertificateAlgorithm, CertificateInfo,
+    CertificateParams, GenError, ParseError,
+};
+pub use clock::Clock;
+pub use connection::{RemoteIdentity, SecuredConnection, SessionParameters};
+#[cfg(feature = "plaintext")]
+pub use error::PlaintextUpgradeError;
+pub use error::{
+    AllowlistError, NoiseUpgradeError, RotationError, SecurityAdapterError, StoreError,
+    TlsUpgradeError, UpgradeError,
 };
-pub(crate) use error::TlsUpgradeError;
 pub use futures_rustls::TlsStream;
-pub(crate) use secure::{secure, EitherSecurityFuture};
-pub use upgrade::Config;
+pub use libp2p_core::upgrade::Version;
+pub use noise::{NoiseConfig, NoiseOutput};
+#[cfg(feature = "plaintext")]
+pub use plaintext::PlaintextConfig;
+pub use policy::{AllowedKeyTypes, Allowlist, Denylist, PeerPolicy};
+pub use protobuf::DecodeError;
+pub use resumption::SessionCache;
+pub use rotation::{RotatingCertResolver, RotationSchedule};
+pub use secure::{secure, EitherSecurityFuture, InboundSecurityFuture, OutboundSecurityFuture};
+pub use select::{SelectSecurityFuture, SelectSecurityUpgrade};
+pub use store::StoredCertificate;
+pub use transport::{Authenticated, Builder, Multiplexed};
+pub use upgrade::{
+    Config, InboundSecurityUpgrade, OutboundSecurityUpgrade, SecurityAdapter, SecurityAdapterFuture,
+};
 
 const P2P_ALPN: &[u8] = b"libp2p";
 
@@ -30,36 +68,120 @@ pub fn make_client_config(
     keypair: &Keypair,
     remote_peer_id: Option<PeerId>,
 ) -> Result<ClientConfig, GenError> {
-    let (certificate, private_key) = certificate::generate(keypair)?;
+    make_client_config_with_params(keypair, remote_peer_id, &CertificateParams::default())
+}
 
-    let mut crypto = ClientConfig::builder()
-        .with_cipher_suites(verifier::CIPHERSUITES)
-        .with_safe_default_kx_groups()
-        .with_protocol_versions(verifier::PROTOCOL_VERSIONS)
-        .expect("Cipher suites and kx groups are configured.")
-        .with_custom_certificate_verifier(Arc::new(Libp2pCertificateVerifier::with_remote_peer_id(
-            remote_peer_id,
-        )))
+/// Create a TLS client configuration for libp2p, whose certificate is generated with `params`.
+pub fn make_client_config_with_params(
+    keypair: &Keypair,
+    remote_peer_id: Option<PeerId>,
+    params: &CertificateParams,
+) -> Result<ClientConfig, GenError> {
+    let (certificate, private_key) = certificate::generate_with_params(keypair, params)?;
+    let verifier = Libp2pCertificateVerifier::with_remote_peer_id(remote_peer_id);
+
+    Ok(client_config(Arc::new(verifier), certificate, private_key))
+}
+
+/// Create a TLS client configuration for libp2p, whose certificate is rotated by `resolver`.
+pub fn make_client_config_with_rotation(
+    resolver: Arc<RotatingCertResolver>,
+    remote_peer_id: Option<PeerId>,
+) -> ClientConfig {
+    let verifier = Libp2pCertificateVerifier::with_remote_peer_id(remote_peer_id);
+
+    client_config_with_rotation(Arc::new(verifier), resolver)
+}
+
+/// Create a TLS client configuration for libp2p, presenting an existing `certificate`.
+fn client_config(
+    verifier: Arc<Libp2pCertificateVerifier>,
+    certificate: rustls::Certificate,
+    private_key: rustls::PrivateKey,
+) -> ClientConfig {
+    let mut crypto = client_config_builder(verifier)
         .with_client_auth_cert(vec![certificate], private_key)
         .expect("Client cert key DER is valid.");
     crypto.alpn_protocols = vec![P2P_ALPN.to_vec()];
 
-    Ok(crypto)
+    crypto
 }
 
-/// Create a TLS server configuration for libp2p.
-pub fn make_server_config(keypair: &Keypair) -> Result<ServerConfig, GenError> {
-    let (certificate, private_key) = certificate::generate(keypair)?;
+/// Create a TLS client configuration for libp2p, whose certificate is rotated by `resolver`.
+fn client_config_with_rotation(
+    verifier: Arc<Libp2pCertificateVerifier>,
+    resolver: Arc<RotatingCertResolver>,
+) -> ClientConfig {
+    let mut crypto = client_config_builder(verifier).with_client_cert_resolver(resolver);
+    crypto.alpn_protocols = vec![P2P_ALPN.to_vec()];
+
+    crypto
+}
 
-    let mut crypto = ServerConfig::builder()
+fn client_config_builder(
+    verifier: Arc<Libp2pCertificateVerifier>,
+) -> ConfigBuilder<ClientConfig, rustls::client::WantsClientCert> {
+    ClientConfig::builder()
         .with_cipher_suites(verifier::CIPHERSUITES)
         .with_safe_default_kx_groups()
         .with_protocol_versions(verifier::PROTOCOL_VERSIONS)
         .expect("Cipher suites and kx groups are configured.")
-        .with_client_cert_verifier(Arc::new(Libp2pCertificateVerifier::new()))
+        .with_custom_certificate_verifier(verifier)
+}
+
+/// Create a TLS server configuration for libp2p.
+pub fn make_server_config(keypair: &Keypair) -> Result<ServerConfig, GenError> {
+    make_server_config_with_params(keypair, &CertificateParams::default())
+}
+
+/// Create a TLS server configuration for libp2p, whose certificate is generated with `params`.
+pub fn make_server_config_with_params(
+    keypair: &Keypair,
+    params: &CertificateParams,
+) -> Result<ServerConfig, GenError> {
+    let (certificate, private_key) = certificate::generate_with_params(keypair, params)?;
+    let verifier = Libp2pCertificateVerifier::new();
+
+    Ok(server_config(Arc::new(verifier), certificate, private_key))
+}
+
+/// Create a TLS server configuration for libp2p, whose certificate is rotated by `resolver`.
+pub fn make_server_config_with_rotation(resolver: Arc<RotatingCertResolver>) -> ServerConfig {
+    server_config_with_rotation(Arc::new(Libp2pCertificateVerifier::new()), resolver)
+}
+
+/// Create a TLS server configuration for libp2p, presenting an existing `certificate`.
+fn server_config(
+    verifier: Arc<Libp2pCertificateVerifier>,
+    certificate: rustls::Certificate,
+    private_key: rustls::PrivateKey,
+) -> ServerConfig {
+    let mut crypto = server_config_builder(verifier)
         .with_single_cert(vec![certificate], private_key)
         .expect("Server cert key DER is valid.");
     crypto.alpn_protocols = vec![P2P_ALPN.to_vec()];
 
-    Ok(crypto)
+    crypto
+}
+
+/// Create a TLS server configuration for libp2p, whose certificate is rotated by `resolver`.
+fn server_config_with_rotation(
+    verifier: Arc<Libp2pCertificateVerifier>,
+    resolver: Arc<RotatingCertResolver>,
+) -> ServerConfig {
+    let mut crypto = server_config_builder(verifier).with_cert_resolver(resolver);
+    crypto.alpn_protocols = vec![P2P_ALPN.to_vec()];
+
+    crypto
+}
+
+fn server_config_builder(
+    verifier: Arc<Libp2pCertificateVerifier>,
+) -> ConfigBuilder<ServerConfig, rustls::server::WantsServerCert> {
+    ServerConfig::builder()
+        .with_cipher_suites(verifier::CIPHERSUITES)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(verifier::PROTOCOL_VERSIONS)
+        .expect("Cipher suites and kx groups are configured.")
+        .with_client_cert_verifier(verifier)
 }
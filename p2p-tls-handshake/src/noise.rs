@@ -0,0 +1,388 @@
+//! Noise security upgrade
+//!
+//! [`NoiseConfig`] secures connections with the `Noise_XX_25519_ChaChaPoly_SHA256` handshake as
+//! specified for libp2p under the protocol ID `/noise`. Both peers send a payload with their
+//! identity key and its signature of their static Noise key, which binds the Noise session to their
+//! [`PeerId`]. Like the TLS upgrade, the dialer checks the identity of the listener as soon as it
+//! is received, and aborts the handshake before authenticating itself on a mismatch.
+//!
+//! See the [Noise specification](https://github.com/libp2p/specs/blob/master/noise/README.md).
+
+use crate::error::NoiseUpgradeError;
+use crate::protobuf;
+use crate::upgrade::{InboundSecurityUpgrade, OutboundSecurityUpgrade};
+use futures::{
+    future::BoxFuture, ready, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt,
+};
+use libp2p_core::upgrade::UpgradeInfo;
+use libp2p_identity::{Keypair, PeerId, PublicKey};
+use snow::{HandshakeState, TransportState};
+use std::{
+    io,
+    iter::{once, Once},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// The Noise protocol used by libp2p.
+const PROTOCOL_NAME: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+/// The prefix of the static key signed with the identity key.
+const STATIC_KEY_DOMAIN: &[u8] = b"noise-libp2p-static-key:";
+/// The length of the longest Noise message.
+const MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+/// The length of the authentication tag of each encrypted message.
+const TAG_LEN: usize = 16;
+/// The length of the longest plaintext of a transport message.
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+
+/// The field numbers of the handshake payload.
+const IDENTITY_KEY: u32 = 1;
+const IDENTITY_SIG: u32 = 2;
+
+/// The Noise security upgrade.
+#[derive(Clone)]
+pub struct NoiseConfig {
+    /// The private static Noise key, shared by all handshakes.
+    static_key: Arc<[u8]>,
+    /// The handshake payload with the identity key and its signature of the static key.
+    payload: Arc<[u8]>,
+}
+
+impl NoiseConfig {
+    /// Creates a configuration with a fresh static key, signed with `identity`.
+    #[allow(clippy::result_large_err)]
+    pub fn new(identity: &Keypair) -> Result<Self, NoiseUpgradeError> {
+        let keypair = builder().generate_keypair()?;
+        let signature = identity.sign(&[STATIC_KEY_DOMAIN, &keypair.public].concat())?;
+
+        let mut payload = Vec::new();
+        protobuf::encode_bytes(
+            IDENTITY_KEY,
+            &identity.public().encode_protobuf(),
+            &mut payload,
+        );
+        protobuf::encode_bytes(IDENTITY_SIG, &signature, &mut payload);
+
+        Ok(Self {
+            static_key: keypair.private.into(),
+            payload: payload.into(),
+        })
+    }
+}
+
+impl UpgradeInfo for NoiseConfig {
+    type Info = &'static str;
+    type InfoIter = Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        once("/noise")
+    }
+}
+
+impl<C> InboundSecurityUpgrade<C> for NoiseConfig
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = NoiseOutput<C>;
+    type Error = NoiseUpgradeError;
+    type Future = BoxFuture<'static, Result<(PeerId, Self::Output), Self::Error>>;
+
+    fn secure_inbound(self, mut socket: C, _: Self::Info) -> Self::Future {
+        async move {
+            let mut state = builder()
+                .local_private_key(&self.static_key)
+                .build_responder()?;
+
+            // -> e
+            receive(&mut socket, &mut state).await?;
+            // <- e, ee, s, es
+            send(&mut socket, &mut state, &self.payload).await?;
+            // -> s, se
+            let payload = receive(&mut socket, &mut state).await?;
+            let peer_id = remote_peer_id(&state, &payload)?;
+
+            Ok((
+                peer_id,
+                NoiseOutput::new(socket, state.into_transport_mode()?),
+            ))
+        }
+        .boxed()
+    }
+}
+
+impl<C> OutboundSecurityUpgrade<C> for NoiseConfig
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = NoiseOutput<C>;
+    type Error = NoiseUpgradeError;
+    type Future = BoxFuture<'static, Result<(PeerId, Self::Output), Self::Error>>;
+
+    fn secure_outbound(
+        self,
+        mut socket: C,
+        _: Self::Info,
+        peer_id: Option<PeerId>,
+    ) -> Self::Future {
+        async move {
+            let mut state = builder()
+                .local_private_key(&self.static_key)
+                .build_initiator()?;
+
+            // -> e
+            send(&mut socket, &mut state, &[]).await?;
+            // <- e, ee, s, es
+            let payload = receive(&mut socket, &mut state).await?;
+            let expected = remote_peer_id(&state, &payload)?;
+
+            if let Some(found) = peer_id.filter(|found| *found != expected) {
+                return Err(NoiseUpgradeError::PeerIdMismatch { expected, found });
+            }
+
+            // -> s, se
+            send(&mut socket, &mut state, &self.payload).await?;
+
+            Ok((
+                expected,
+                NoiseOutput::new(socket, state.into_transport_mode()?),
+            ))
+        }
+        .boxed()
+    }
+}
+
+fn builder() -> snow::Builder<'static> {
+    snow::Builder::new(
+        PROTOCOL_NAME
+            .parse()
+            .expect("Noise protocol name is valid."),
+    )
+}
+
+/// Writes the next handshake message, carrying `payload`.
+async fn send<C>(
+    socket: &mut C,
+    state: &mut HandshakeState,
+    payload: &[u8],
+) -> Result<(), NoiseUpgradeError>
+where
+    C: AsyncWrite + Unpin,
+{
+    let mut message = vec![0; MAX_MESSAGE_LEN + 2];
+    let len = state.write_message(payload, &mut message[2..])?;
+    message[..2].copy_from_slice(&(len as u16).to_be_bytes());
+    message.truncate(len + 2);
+
+    socket.write_all(&message).await?;
+    socket.flush().await?;
+
+    Ok(())
+}
+
+/// Reads the next handshake message, returning its payload.
+async fn receive<C>(
+    socket: &mut C,
+    state: &mut HandshakeState,
+) -> Result<Vec<u8>, NoiseUpgradeError>
+where
+    C: AsyncRead + Unpin,
+{
+    let mut len = [0; 2];
+    socket.read_exact(&mut len).await?;
+    let mut message = vec![0; u16::from_be_bytes(len).into()];
+    socket.read_exact(&mut message).await?;
+
+    let mut payload = vec![0; message.len()];
+    let len = state.read_message(&message, &mut payload)?;
+    payload.truncate(len);
+
+    Ok(payload)
+}
+
+/// Verifies that the identity key of the remote `payload` signed the remote static key, and
+/// returns the [`PeerId`] of the identity key.
+#[allow(clippy::result_large_err)]
+fn remote_peer_id(state: &HandshakeState, payload: &[u8]) -> Result<PeerId, NoiseUpgradeError> {
+    let mut identity_key = None;
+    let mut signature = None;
+    for (number, value) in protobuf::decode_bytes_fields(payload)? {
+        match number {
+            IDENTITY_KEY => identity_key = Some(value),
+            IDENTITY_SIG => signature = Some(value),
+            _ => {}
+        }
+    }
+
+    let identity_key = PublicKey::try_decode_protobuf(identity_key.unwrap_or_default())?;
+    // The remote static key is received along with the payload, were it missing the signature
+    // would not verify.
+    let static_key = state.get_remote_static().unwrap_or_default();
+    let signature = signature.unwrap_or_default();
+    if !identity_key.verify(&[STATIC_KEY_DOMAIN, static_key].concat(), signature) {
+        return Err(NoiseUpgradeError::BadSignature);
+    }
+
+    Ok(identity_key.to_peer_id())
+}
+
+/// A connection secured by the Noise handshake.
+///
+/// What is written is sent in encrypted messages of up to 65535 bytes, each once it is full or
+/// the connection is flushed.
+pub struct NoiseOutput<C> {
+    io: C,
+    state: TransportState,
+    /// The message being received, along with its length prefix.
+    receiving: Vec<u8>,
+    received: usize,
+    /// The plaintext of the last message received, from `read` on.
+    decrypted: Vec<u8>,
+    read: usize,
+    /// The plaintext of the next message to send.
+    plaintext: Vec<u8>,
+    /// The message being sent, along with its length prefix, from `sent` on.
+    sending: Vec<u8>,
+    sent: usize,
+}
+
+impl<C> NoiseOutput<C> {
+    fn new(io: C, state: TransportState) -> Self {
+        Self {
+            io,
+            state,
+            receiving: vec![0; 2],
+            received: 0,
+            decrypted: Vec::new(),
+            read: 0,
+            plaintext: Vec::new(),
+            sending: Vec::new(),
+            sent: 0,
+        }
+    }
+
+    /// The underlying connection.
+    pub fn get_ref(&self) -> &C {
+        &self.io
+    }
+}
+
+impl<C: AsyncWrite + Unpin> NoiseOutput<C> {
+    /// Encrypts the pending plaintext into the next message to send.
+    fn seal(&mut self) -> io::Result<()> {
+        let len = self.plaintext.len() + TAG_LEN;
+        self.sending.clear();
+        self.sending.extend_from_slice(&(len as u16).to_be_bytes());
+        self.sending.resize(len + 2, 0);
+        self.state
+            .write_message(&self.plaintext, &mut self.sending[2..])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.plaintext.clear();
+        self.sent = 0;
+
+        Ok(())
+    }
+
+    /// Sends what is left of the message being sent.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.sent < self.sending.len() {
+            let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.sending[self.sent..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.sent += n;
+        }
+        self.sending.clear();
+        self.sent = 0;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: AsyncRead + Unpin> AsyncRead for NoiseOutput<C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        while this.read == this.decrypted.len() {
+            let n =
+                ready!(Pin::new(&mut this.io).poll_read(cx, &mut this.receiving[this.received..]))?;
+            if n == 0 {
+                if this.received == 0 {
+                    return Poll::Ready(Ok(0));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.received += n;
+
+            if this.received < this.receiving.len() {
+                continue;
+            }
+            if this.receiving.len() == 2 {
+                let len = usize::from(u16::from_be_bytes([this.receiving[0], this.receiving[1]]));
+                if len < TAG_LEN {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Noise message shorter than its tag",
+                    )));
+                }
+                this.receiving.resize(len + 2, 0);
+                continue;
+            }
+
+            this.decrypted.resize(this.receiving.len() - 2, 0);
+            let len = this
+                .state
+                .read_message(&this.receiving[2..], &mut this.decrypted)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            this.decrypted.truncate(len);
+            this.read = 0;
+            this.receiving.truncate(2);
+            this.received = 0;
+        }
+
+        let n = buf.len().min(this.decrypted.len() - this.read);
+        buf[..n].copy_from_slice(&this.decrypted[this.read..this.read + n]);
+        this.read += n;
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<C: AsyncWrite + Unpin> AsyncWrite for NoiseOutput<C> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        if this.plaintext.len() == MAX_PLAINTEXT_LEN {
+            this.seal()?;
+            ready!(this.poll_send(cx))?;
+        }
+
+        let n = buf.len().min(MAX_PLAINTEXT_LEN - this.plaintext.len());
+        this.plaintext.extend_from_slice(&buf[..n]);
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        if !this.plaintext.is_empty() {
+            this.seal()?;
+            ready!(this.poll_send(cx))?;
+        }
+
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.io).poll_close(cx)
+    }
+}
@@ -0,0 +1,162 @@
+//! Plaintext security upgrade
+//!
+//! [`PlaintextConfig`] implements the `/plaintext/2.0.0` protocol of libp2p: both peers send an
+//! `Exchange` message with their [`PeerId`] and public key, and the connection is then used as is.
+//! Nothing is encrypted and the peers do not prove that they own their keys, so it is only meant
+//! for tests and debugging, e.g. of the upgrades applied after the security upgrade. It also
+//! serves as a minimal implementation of [`InboundSecurityUpgrade`]/[`OutboundSecurityUpgrade`].
+//!
+//! See the [plaintext specification](https://github.com/libp2p/specs/blob/master/plaintext/README.md).
+
+use crate::error::PlaintextUpgradeError;
+use crate::protobuf;
+use crate::upgrade::{InboundSecurityUpgrade, OutboundSecurityUpgrade};
+use futures::{future::BoxFuture, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt};
+use libp2p_core::upgrade::UpgradeInfo;
+use libp2p_identity::{Keypair, PeerId, PublicKey};
+use std::{
+    io,
+    iter::{once, Once},
+};
+
+/// The length of the longest `Exchange` message accepted from the remote peer.
+const MAX_MESSAGE_LEN: usize = 4096;
+
+/// The field numbers of the `Exchange` message.
+const ID: u32 = 1;
+const PUBKEY: u32 = 2;
+
+/// The plaintext security upgrade.
+#[derive(Debug, Clone)]
+pub struct PlaintextConfig {
+    local_public_key: PublicKey,
+}
+
+impl PlaintextConfig {
+    /// Creates a configuration announcing the public key of `identity`.
+    pub fn new(identity: &Keypair) -> Self {
+        Self {
+            local_public_key: identity.public(),
+        }
+    }
+
+    /// Sends our `Exchange` message and returns the [`PeerId`] of the remote one.
+    async fn exchange<C>(self, socket: &mut C) -> Result<PeerId, PlaintextUpgradeError>
+    where
+        C: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut exchange = Vec::new();
+        protobuf::encode_bytes(
+            ID,
+            &self.local_public_key.to_peer_id().to_bytes(),
+            &mut exchange,
+        );
+        protobuf::encode_bytes(
+            PUBKEY,
+            &self.local_public_key.encode_protobuf(),
+            &mut exchange,
+        );
+        let mut message = Vec::new();
+        protobuf::encode_varint(exchange.len() as u64, &mut message);
+        message.extend_from_slice(&exchange);
+        socket.write_all(&message).await?;
+        socket.flush().await?;
+
+        let mut exchange = vec![0; read_len(socket).await?];
+        socket.read_exact(&mut exchange).await?;
+        let mut id = None;
+        let mut pubkey = None;
+        for (number, value) in protobuf::decode_bytes_fields(&exchange)? {
+            match number {
+                ID => id = Some(value),
+                PUBKEY => pubkey = Some(value),
+                _ => {}
+            }
+        }
+
+        let public_key = PublicKey::try_decode_protobuf(pubkey.unwrap_or_default())?;
+        let peer_id = PeerId::from_bytes(id.unwrap_or_default())?;
+        if peer_id != public_key.to_peer_id() {
+            return Err(PlaintextUpgradeError::PublicKeyMismatch(peer_id));
+        }
+
+        Ok(peer_id)
+    }
+}
+
+impl UpgradeInfo for PlaintextConfig {
+    type Info = &'static str;
+    type InfoIter = Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        once("/plaintext/2.0.0")
+    }
+}
+
+impl<C> InboundSecurityUpgrade<C> for PlaintextConfig
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = C;
+    type Error = PlaintextUpgradeError;
+    type Future = BoxFuture<'static, Result<(PeerId, Self::Output), Self::Error>>;
+
+    fn secure_inbound(self, mut socket: C, _: Self::Info) -> Self::Future {
+        async move {
+            let peer_id = self.exchange(&mut socket).await?;
+
+            Ok((peer_id, socket))
+        }
+        .boxed()
+    }
+}
+
+impl<C> OutboundSecurityUpgrade<C> for PlaintextConfig
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = C;
+    type Error = PlaintextUpgradeError;
+    type Future = BoxFuture<'static, Result<(PeerId, Self::Output), Self::Error>>;
+
+    fn secure_outbound(
+        self,
+        mut socket: C,
+        _: Self::Info,
+        peer_id: Option<PeerId>,
+    ) -> Self::Future {
+        async move {
+            let expected = self.exchange(&mut socket).await?;
+
+            if let Some(found) = peer_id.filter(|found| *found != expected) {
+                return Err(PlaintextUpgradeError::PeerIdMismatch { expected, found });
+            }
+
+            Ok((expected, socket))
+        }
+        .boxed()
+    }
+}
+
+/// Reads the unsigned varint length prefix of a message, one byte at a time so that nothing
+/// beyond the message is read.
+async fn read_len<C>(socket: &mut C) -> Result<usize, PlaintextUpgradeError>
+where
+    C: AsyncRead + Unpin,
+{
+    let mut prefix = Vec::new();
+    while prefix.len() < protobuf::MAX_VARINT_LEN {
+        let mut byte = [0];
+        socket.read_exact(&mut byte).await?;
+        prefix.push(byte[0]);
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let len = protobuf::decode_varint(&mut &prefix[..])?;
+    match usize::try_from(len) {
+        Ok(len) if len <= MAX_MESSAGE_LEN => Ok(len),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Exchange message too long").into()),
+    }
+}
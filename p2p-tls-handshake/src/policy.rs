@@ -0,0 +1,143 @@
+//! Peer admission policies
+//!
+//! A [`PeerPolicy`] decides which peers may complete a handshake. Policies are consulted by the
+//! certificate verifier right after the peer certificate has been verified, so that rejected
+//! peers are turned away inside the TLS handshake with an `access_denied` alert.
+
+use libp2p_identity::{KeyType, PeerId, PublicKey};
+use std::collections::HashSet;
+
+/// Decides whether a peer may complete a handshake.
+pub trait PeerPolicy: Send + Sync {
+    /// Returns whether the peer with the given host key is admitted.
+    fn admit(&self, public_key: &PublicKey) -> bool;
+}
+
+impl<F> PeerPolicy for F
+where
+    F: Fn(&PublicKey) -> bool + Send + Sync,
+{
+    fn admit(&self, public_key: &PublicKey) -> bool {
+        self(public_key)
+    }
+}
+
+/// Admits only the listed peers.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    peers: HashSet<PeerId>,
+}
+
+impl Allowlist {
+    pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Self {
+        peers.into_iter().collect()
+    }
+}
+
+impl FromIterator<PeerId> for Allowlist {
+    fn from_iter<I: IntoIterator<Item = PeerId>>(peers: I) -> Self {
+        Self {
+            peers: peers.into_iter().collect(),
+        }
+    }
+}
+
+impl PeerPolicy for Allowlist {
+    fn admit(&self, public_key: &PublicKey) -> bool {
+        self.peers.contains(&public_key.to_peer_id())
+    }
+}
+
+/// Admits every peer except the listed ones.
+#[derive(Debug, Clone, Default)]
+pub struct Denylist {
+    peers: HashSet<PeerId>,
+}
+
+impl Denylist {
+    pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Self {
+        peers.into_iter().collect()
+    }
+}
+
+impl FromIterator<PeerId> for Denylist {
+    fn from_iter<I: IntoIterator<Item = PeerId>>(peers: I) -> Self {
+        Self {
+            peers: peers.into_iter().collect(),
+        }
+    }
+}
+
+impl PeerPolicy for Denylist {
+    fn admit(&self, public_key: &PublicKey) -> bool {
+        !self.peers.contains(&public_key.to_peer_id())
+    }
+}
+
+/// Admits only peers whose host key is of one of the listed types.
+#[derive(Debug, Default)]
+pub struct AllowedKeyTypes {
+    key_types: Vec<KeyType>,
+}
+
+impl AllowedKeyTypes {
+    pub fn new(key_types: impl IntoIterator<Item = KeyType>) -> Self {
+        Self {
+            key_types: key_types.into_iter().collect(),
+        }
+    }
+}
+
+impl PeerPolicy for AllowedKeyTypes {
+    fn admit(&self, public_key: &PublicKey) -> bool {
+        self.key_types.contains(&public_key.key_type())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_identity::Keypair;
+
+    #[test]
+    fn allowlist_admits_listed_peers() {
+        let listed = Keypair::generate_ed25519().public();
+        let unlisted = Keypair::generate_ed25519().public();
+
+        let policy = Allowlist::new([listed.to_peer_id()]);
+
+        assert!(policy.admit(&listed));
+        assert!(!policy.admit(&unlisted));
+    }
+
+    #[test]
+    fn denylist_rejects_listed_peers() {
+        let listed = Keypair::generate_ed25519().public();
+        let unlisted = Keypair::generate_ed25519().public();
+
+        let policy = Denylist::new([listed.to_peer_id()]);
+
+        assert!(!policy.admit(&listed));
+        assert!(policy.admit(&unlisted));
+    }
+
+    #[test]
+    fn allowed_key_types_admit_listed_key_types() {
+        let policy = AllowedKeyTypes::new([KeyType::Ed25519, KeyType::Ecdsa]);
+
+        assert!(policy.admit(&Keypair::generate_ed25519().public()));
+        assert!(policy.admit(&Keypair::generate_ecdsa().public()));
+        assert!(!policy.admit(&Keypair::generate_secp256k1().public()));
+    }
+
+    #[test]
+    fn closures_are_policies() {
+        let admitted = Keypair::generate_ed25519().public();
+        let peer_id = admitted.to_peer_id();
+
+        let policy = move |public_key: &PublicKey| public_key.to_peer_id() == peer_id;
+
+        assert!(policy.admit(&admitted));
+        assert!(!policy.admit(&Keypair::generate_ed25519().public()));
+    }
+}
@@ -0,0 +1,123 @@
+//! Minimal protobuf encoding of the handshake payloads
+//!
+//! The payloads exchanged by the handshakes only consist of length-delimited fields, so they are
+//! encoded by hand instead of through generated code. Fields of other wire types are skipped when
+//! decoding, as protobuf requires for unknown fields.
+
+use std::fmt;
+
+/// The wire type of varint fields.
+const VARINT: u64 = 0;
+/// The wire type of 64-bit fields.
+const I64: u64 = 1;
+/// The wire type of length-delimited fields.
+const LEN: u64 = 2;
+/// The wire type of 32-bit fields.
+const I32: u64 = 5;
+/// The length of the longest varint, encoding a `u64`.
+pub(crate) const MAX_VARINT_LEN: usize = 10;
+
+/// Error that can happen when decoding a protobuf message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError(&'static str);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid protobuf message: {}", self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Appends the length-delimited field `number` with `value` to `buf`.
+pub(crate) fn encode_bytes(number: u32, value: &[u8], buf: &mut Vec<u8>) {
+    encode_varint(u64::from(number) << 3 | LEN, buf);
+    encode_varint(value.len() as u64, buf);
+    buf.extend_from_slice(value);
+}
+
+/// Returns the length-delimited fields of `message` as pairs of field number and value.
+pub(crate) fn decode_bytes_fields(mut message: &[u8]) -> Result<Vec<(u32, &[u8])>, DecodeError> {
+    let mut fields = Vec::new();
+    while !message.is_empty() {
+        let key = decode_varint(&mut message)?;
+        let number = u32::try_from(key >> 3).map_err(|_| DecodeError("field number too large"))?;
+        let len = match key & 0x7 {
+            VARINT => {
+                decode_varint(&mut message)?;
+                continue;
+            }
+            I64 => 8,
+            LEN => usize::try_from(decode_varint(&mut message)?)
+                .map_err(|_| DecodeError("field too long"))?,
+            I32 => 4,
+            _ => return Err(DecodeError("unsupported wire type")),
+        };
+        if len > message.len() {
+            return Err(DecodeError("truncated field"));
+        }
+        let (value, rest) = message.split_at(len);
+        if key & 0x7 == LEN {
+            fields.push((number, value));
+        }
+        message = rest;
+    }
+
+    Ok(fields)
+}
+
+/// Appends `value` to `buf` as unsigned varint.
+pub(crate) fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Decodes the unsigned varint at the start of `buf`, advancing `buf` past it.
+pub(crate) fn decode_varint(buf: &mut &[u8]) -> Result<u64, DecodeError> {
+    let mut value = 0;
+    for (i, byte) in buf.iter().enumerate().take(MAX_VARINT_LEN) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Ok(value);
+        }
+    }
+
+    Err(DecodeError("truncated or overlong varint"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_bytes_fields() {
+        let mut buf = Vec::new();
+        encode_bytes(1, b"key", &mut buf);
+        encode_bytes(2, &[0; 200], &mut buf);
+        assert_eq!(&buf[..5], b"\x0a\x03key");
+
+        let fields = decode_bytes_fields(&buf).unwrap();
+        assert_eq!(fields, [(1, &b"key"[..]), (2, &[0; 200][..])]);
+    }
+
+    #[test]
+    fn skips_fields_of_other_wire_types() {
+        // A varint field 3, a 32-bit field 5 and a 64-bit field 6 around a bytes field 1.
+        let mut buf = vec![0x18, 0x96, 0x01, 0x2d, 1, 2, 3, 4];
+        encode_bytes(1, b"key", &mut buf);
+        buf.extend_from_slice(&[0x31, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        assert_eq!(decode_bytes_fields(&buf).unwrap(), [(1, &b"key"[..])]);
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        assert!(decode_bytes_fields(b"\x0a\x05key").is_err());
+        assert!(decode_bytes_fields(b"\x0a").is_err());
+        assert!(decode_bytes_fields(b"\x0b").is_err());
+    }
+}
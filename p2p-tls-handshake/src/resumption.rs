@@ -0,0 +1,313 @@
+//! TLS session resumption
+//!
+//! Reconnecting to a peer we were recently connected to resumes the previous TLS session instead
+//! of performing a full handshake, which saves the key exchange, the handshake signatures and the
+//! certificate verification on both sides.
+//!
+//! The dialer keeps the session tickets it receives in a [`SessionCache`], keyed by the
+//! [`PeerId`] of the listener, and only offers them when dialing that same peer ID again. Tickets
+//! are only kept once the peer ID of the listener has been verified. The listener keeps no state
+//! at all: its tickets are encrypted with keys of its [`SessionCache`] and bound to the
+//! certificate it presented, so that it only resumes sessions of its current certificate.
+
+use crate::connection::fingerprint;
+use crate::upgrade::LocalCertificate;
+use libp2p_identity::PeerId;
+use rustls::client::{ClientSessionStore, Tls12ClientSessionValue, Tls13ClientSessionValue};
+use rustls::server::{ProducesTickets, ResolvesServerCert};
+use rustls::{Certificate, NamedGroup, ServerName, Ticketer};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many peers a [`SessionCache`] keeps sessions for by default.
+const DEFAULT_CAPACITY: usize = 256;
+/// How long sessions are resumed by default.
+const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+/// How many tickets are kept per peer, out of those sent by the listener after every handshake.
+const MAX_TICKETS_PER_PEER: usize = 8;
+
+/// The sessions that can be resumed, both as dialer and as listener.
+///
+/// Handles are cheap to clone and share the same sessions.
+#[derive(Clone)]
+pub struct SessionCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    capacity: usize,
+    ttl: Duration,
+    /// Encrypts the tickets issued as listener.
+    ticketer: Arc<dyn ProducesTickets>,
+    /// The tickets received as dialer.
+    peers: Mutex<HashMap<PeerId, PeerSessions>>,
+}
+
+/// The sessions to a single peer.
+struct PeerSessions {
+    tickets: VecDeque<Ticket>,
+    kx_hint: Option<NamedGroup>,
+    updated_at: Instant,
+}
+
+/// A ticket received as dialer.
+struct Ticket {
+    value: Tls13ClientSessionValue,
+    /// The certificate we presented in the session of the ticket.
+    local_certificate: Certificate,
+    received_at: Instant,
+}
+
+impl SessionCache {
+    /// Creates a cache of the sessions to at most `capacity` peers, which are resumed for up to
+    /// `ttl` after they were established.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                capacity,
+                ttl,
+                ticketer: Ticketer::new().expect("Ticket keys are generated."),
+                peers: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// The number of peers whose sessions can be resumed.
+    pub fn len(&self) -> usize {
+        self.inner.peers().len()
+    }
+
+    /// Whether no session can be resumed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the sessions to `peer_id`, so that the next connection to it performs a full
+    /// handshake.
+    pub fn remove(&self, peer_id: &PeerId) {
+        self.inner.peers().remove(peer_id);
+    }
+
+    /// Returns the sessions of a single handshake dialing `peer_id`.
+    ///
+    /// Sessions are only resumed when the peer ID to dial is known.
+    pub(crate) fn outbound(&self, peer_id: Option<PeerId>) -> Arc<OutboundSessions> {
+        Arc::new(OutboundSessions {
+            cache: self.inner.clone(),
+            peer_id,
+            offered: Mutex::new(None),
+            established: OnceLock::new(),
+        })
+    }
+
+    /// Returns the ticketer of a single handshake presenting the certificate recorded by `local`.
+    pub(crate) fn inbound(
+        &self,
+        local: Arc<LocalCertificate<dyn ResolvesServerCert>>,
+    ) -> Arc<InboundTickets> {
+        Arc::new(InboundTickets {
+            ticketer: self.inner.ticketer.clone(),
+            ttl: self.inner.ttl,
+            local,
+        })
+    }
+}
+
+impl Default for SessionCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_TTL)
+    }
+}
+
+impl Inner {
+    fn peers(&self) -> MutexGuard<'_, HashMap<PeerId, PeerSessions>> {
+        self.peers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the sessions to `peer_id`, evicting the least recently updated peer to make room
+    /// if needed.
+    fn update<R>(&self, peer_id: PeerId, f: impl FnOnce(&mut PeerSessions) -> R) -> Option<R> {
+        let mut peers = self.peers();
+        if !peers.contains_key(&peer_id) {
+            if self.capacity == 0 {
+                return None;
+            }
+            if peers.len() == self.capacity {
+                let oldest = peers
+                    .iter()
+                    .min_by_key(|(_, sessions)| sessions.updated_at)
+                    .map(|(peer_id, _)| *peer_id)
+                    .expect("Cache is full.");
+                peers.remove(&oldest);
+            }
+        }
+
+        let sessions = peers.entry(peer_id).or_insert_with(|| PeerSessions {
+            tickets: VecDeque::new(),
+            kx_hint: None,
+            updated_at: Instant::now(),
+        });
+        sessions.updated_at = Instant::now();
+
+        Some(f(sessions))
+    }
+
+    fn insert(&self, peer_id: PeerId, ticket: Ticket) {
+        self.update(peer_id, |sessions| {
+            if sessions.tickets.len() == MAX_TICKETS_PER_PEER {
+                sessions.tickets.pop_front();
+            }
+            sessions.tickets.push_back(ticket);
+        });
+    }
+
+    /// Takes the most recent ticket to `peer_id` that has not expired yet.
+    fn take(&self, peer_id: &PeerId) -> Option<Ticket> {
+        let mut peers = self.peers();
+        let sessions = peers.get_mut(peer_id)?;
+        sessions
+            .tickets
+            .retain(|ticket| ticket.received_at.elapsed() < self.ttl);
+
+        sessions.tickets.pop_back()
+    }
+}
+
+/// The sessions of a single handshake as dialer.
+pub(crate) struct OutboundSessions {
+    cache: Arc<Inner>,
+    peer_id: Option<PeerId>,
+    /// The local certificate of the session offered to be resumed, if any.
+    offered: Mutex<Option<Certificate>>,
+    /// The local certificate of the connection, set once the peer ID of the listener has been
+    /// verified.
+    established: OnceLock<Certificate>,
+}
+
+impl OutboundSessions {
+    /// The certificate we presented in the session that was offered to be resumed, if any.
+    pub(crate) fn offered_certificate(&self) -> Option<Certificate> {
+        self.offered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Keeps the tickets received from now on, since the peer ID of the listener has been
+    /// verified.
+    pub(crate) fn establish(&self, local_certificate: Certificate) {
+        let _ = self.established.set(local_certificate);
+    }
+}
+
+impl ClientSessionStore for OutboundSessions {
+    fn set_kx_hint(&self, _: &ServerName, group: NamedGroup) {
+        if let Some(peer_id) = self.peer_id {
+            self.cache
+                .update(peer_id, |sessions| sessions.kx_hint = Some(group));
+        }
+    }
+
+    fn kx_hint(&self, _: &ServerName) -> Option<NamedGroup> {
+        self.cache.peers().get(&self.peer_id?)?.kx_hint
+    }
+
+    // TLS 1.2 is not supported.
+    fn set_tls12_session(&self, _: &ServerName, _: Tls12ClientSessionValue) {}
+
+    fn tls12_session(&self, _: &ServerName) -> Option<Tls12ClientSessionValue> {
+        None
+    }
+
+    fn remove_tls12_session(&self, _: &ServerName) {}
+
+    fn insert_tls13_ticket(&self, _: &ServerName, value: Tls13ClientSessionValue) {
+        let (Some(peer_id), Some(local_certificate)) = (self.peer_id, self.established.get())
+        else {
+            return;
+        };
+
+        self.cache.insert(
+            peer_id,
+            Ticket {
+                value,
+                local_certificate: local_certificate.clone(),
+                received_at: Instant::now(),
+            },
+        );
+    }
+
+    fn take_tls13_ticket(&self, _: &ServerName) -> Option<Tls13ClientSessionValue> {
+        let ticket = self.cache.take(&self.peer_id?)?;
+        *self.offered.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some(ticket.local_certificate);
+
+        Some(ticket.value)
+    }
+}
+
+/// The ticketer of a single handshake as listener.
+///
+/// Tickets carry the fingerprint of the certificate presented and the time they were issued at,
+/// in front of the session itself.
+pub(crate) struct InboundTickets {
+    ticketer: Arc<dyn ProducesTickets>,
+    ttl: Duration,
+    local: Arc<LocalCertificate<dyn ResolvesServerCert>>,
+}
+
+impl InboundTickets {
+    fn local_fingerprint(&self) -> Option<[u8; 32]> {
+        self.local
+            .certificate()
+            .map(|certificate| fingerprint(&certificate))
+    }
+}
+
+impl ProducesTickets for InboundTickets {
+    fn enabled(&self) -> bool {
+        self.ticketer.enabled()
+    }
+
+    fn lifetime(&self) -> u32 {
+        let ttl = u32::try_from(self.ttl.as_secs()).unwrap_or(u32::MAX);
+
+        self.ticketer.lifetime().min(ttl)
+    }
+
+    fn encrypt(&self, plain: &[u8]) -> Option<Vec<u8>> {
+        let issued_at = unix_time(SystemTime::now());
+
+        self.ticketer.encrypt(
+            &[
+                &self.local_fingerprint()?[..],
+                &issued_at.to_be_bytes(),
+                plain,
+            ]
+            .concat(),
+        )
+    }
+
+    fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>> {
+        let plain = self.ticketer.decrypt(cipher)?;
+        let (bound_fingerprint, rest) = plain.split_at_checked(32)?;
+        let (issued_at, session) = rest.split_at_checked(8)?;
+        let issued_at = u64::from_be_bytes(issued_at.try_into().ok()?);
+
+        if bound_fingerprint != self.local_fingerprint()? {
+            return None;
+        }
+        if unix_time(SystemTime::now()).saturating_sub(issued_at) >= self.ttl.as_secs() {
+            return None;
+        }
+
+        Some(session.to_vec())
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,428 @@
+//! Automatic certificate rotation
+//!
+//! A [`RotatingCertResolver`] hands the current certificate to every new handshake and replaces
+//! it with a freshly generated one once it is due according to its [`RotationSchedule`].
+//! Certificates are generated ahead of time by a background thread, which stops once the resolver
+//! is dropped, so that handshakes never wait for a rotation. Established connections keep the
+//! certificate they were secured with.
+
+use crate::certificate::{self, CertificateParams};
+use crate::error::RotationError;
+use libp2p_identity::Keypair;
+use rustls::client::ResolvesClientCert;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// The shortest time the rotation thread waits between two checks of the schedule.
+const MIN_WAIT: Duration = Duration::from_millis(10);
+/// The longest time the rotation thread waits between two checks of the schedule, which bounds
+/// how long it outlives its resolver.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+/// The shortest time a certificate is handed out before it is due for rotation, so that the
+/// rotation thread does not generate certificates back to back.
+const MIN_LIFETIME: Duration = Duration::from_secs(1);
+
+/// When a [`RotatingCertResolver`] replaces its certificate.
+#[derive(Debug, Clone, Default)]
+pub struct RotationSchedule {
+    interval: Option<Duration>,
+    validity: Option<Duration>,
+    renew_before_expiry: Duration,
+}
+
+impl RotationSchedule {
+    /// Creates a schedule that only rotates expired certificates.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rotates the certificate once it is older than `interval`, which must be at least one second.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Makes every generated certificate valid for `validity`, starting when it is generated.
+    ///
+    /// This overrides the validity period of the [`CertificateParams`] of the resolver.
+    pub fn with_validity(mut self, validity: Duration) -> Self {
+        self.validity = Some(validity);
+        self
+    }
+
+    /// Rotates the certificate `margin` before it expires.
+    ///
+    /// The margin must leave every certificate at least one second before it is due.
+    pub fn with_renew_before_expiry(mut self, margin: Duration) -> Self {
+        self.renew_before_expiry = margin;
+        self
+    }
+
+    /// Rejects schedules that would rotate certificates back to back.
+    fn validate(&self) -> Result<(), RotationError> {
+        if self
+            .interval
+            .is_some_and(|interval| interval < MIN_LIFETIME)
+        {
+            return Err(RotationError::IntervalTooShort(MIN_LIFETIME));
+        }
+        let min_validity = self.renew_before_expiry.checked_add(MIN_LIFETIME);
+        if self
+            .validity
+            .is_some_and(|validity| min_validity.is_none_or(|min| validity < min))
+        {
+            return Err(RotationError::RenewalTooSoon(MIN_LIFETIME));
+        }
+
+        Ok(())
+    }
+}
+
+/// The certificate currently handed out by a [`RotatingCertResolver`].
+struct Current {
+    key: Arc<CertifiedKey>,
+    generated_at: SystemTime,
+    not_after: SystemTime,
+}
+
+/// Resolves the certificate of both the server and the client side of a handshake, rotating it
+/// according to a [`RotationSchedule`].
+pub struct RotatingCertResolver {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    identity: Keypair,
+    params: CertificateParams,
+    schedule: RotationSchedule,
+    current: RwLock<Current>,
+}
+
+impl RotatingCertResolver {
+    /// Creates a resolver for `identity`, whose certificates are generated according to `params`.
+    ///
+    /// Fails if the schedule or the validity period of `params` would make the first certificate
+    /// due for rotation right away.
+    pub fn new(
+        identity: &Keypair,
+        params: CertificateParams,
+        schedule: RotationSchedule,
+    ) -> Result<Self, RotationError> {
+        schedule.validate()?;
+        let current = generate(identity, &params, &schedule, SystemTime::now())?;
+        let inner = Arc::new(Inner {
+            identity: identity.clone(),
+            params,
+            schedule,
+            current: RwLock::new(current),
+        });
+
+        let weak = Arc::downgrade(&inner);
+        if let Err(e) = thread::Builder::new()
+            .name("certificate-rotation".into())
+            .spawn(move || rotate_on_schedule(weak))
+        {
+            tracing::warn!(
+                peer_id=%identity.public().to_peer_id(),
+                "Failed to start certificate rotation, only explicit rotations apply: {e}"
+            );
+        }
+
+        Ok(Self { inner })
+    }
+
+    /// The certificate handed out to new handshakes.
+    pub fn certificate(&self) -> rustls::Certificate {
+        self.resolve().cert[0].clone()
+    }
+
+    /// Replaces the certificate immediately, regardless of the schedule.
+    pub fn rotate(&self) -> Result<(), RotationError> {
+        self.inner.rotate(SystemTime::now())
+    }
+
+    /// Returns the current certificate.
+    fn resolve(&self) -> Arc<CertifiedKey> {
+        self.inner.current().key.clone()
+    }
+}
+
+impl Inner {
+    fn current(&self) -> RwLockReadGuard<'_, Current> {
+        self.current.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// When the current certificate is due for rotation.
+    fn due_at(&self) -> SystemTime {
+        let current = self.current();
+        let renew_at = current
+            .not_after
+            .checked_sub(self.schedule.renew_before_expiry)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let rotate_at = self
+            .schedule
+            .interval
+            .and_then(|interval| current.generated_at.checked_add(interval));
+
+        rotate_at.map_or(renew_at, |rotate_at| rotate_at.min(renew_at))
+    }
+
+    /// Generates a new certificate and swaps it in, holding the lock only for the swap.
+    fn rotate(&self, now: SystemTime) -> Result<(), RotationError> {
+        let rotated = generate(&self.identity, &self.params, &self.schedule, now)?;
+        let not_after = rotated.not_after;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = rotated;
+        tracing::info!(
+            peer_id=%self.identity.public().to_peer_id(),
+            ?not_after,
+            "Rotated certificate"
+        );
+
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for RotatingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(RotatingCertResolver::resolve(self))
+    }
+}
+
+impl ResolvesClientCert for RotatingCertResolver {
+    fn resolve(
+        &self,
+        _acceptable_issuers: &[&[u8]],
+        _sigschemes: &[rustls::SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(RotatingCertResolver::resolve(self))
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Rotates the certificate whenever it is due, until the resolver is dropped.
+fn rotate_on_schedule(inner: Weak<Inner>) {
+    loop {
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        let now = SystemTime::now();
+        let mut retry = false;
+        if inner.due_at() <= now {
+            if let Err(e) = inner.rotate(now) {
+                tracing::warn!(
+                    peer_id=%inner.identity.public().to_peer_id(),
+                    "Failed to rotate certificate, keeping the current one: {e}"
+                );
+                retry = true;
+            }
+        }
+        // A failed rotation is retried later rather than as soon as possible, as it likely fails
+        // again, e.g. once the fixed validity period of the parameters has passed.
+        let wait = if retry {
+            MAX_WAIT
+        } else {
+            inner
+                .due_at()
+                .duration_since(SystemTime::now())
+                .unwrap_or_default()
+                .clamp(MIN_WAIT, MAX_WAIT)
+        };
+        drop(inner);
+        thread::sleep(wait);
+    }
+}
+
+/// Generates a certificate at `now`, failing if it would be due for rotation right away.
+fn generate(
+    identity: &Keypair,
+    params: &CertificateParams,
+    schedule: &RotationSchedule,
+    now: SystemTime,
+) -> Result<Current, RotationError> {
+    let (certificate, private_key) = match schedule.validity {
+        Some(validity) => {
+            let not_after = now
+                .checked_add(validity)
+                .ok_or(RotationError::ValidityOutOfRange)?;
+            let params = params.clone().with_validity(now, not_after);
+            certificate::generate_with_params(identity, &params)?
+        }
+        None => certificate::generate_with_params(identity, params)?,
+    };
+    let not_after = certificate::inspect(&certificate)?.not_after();
+    if not_after <= now {
+        return Err(RotationError::Expired(not_after));
+    }
+    let lifetime = not_after
+        .checked_sub(schedule.renew_before_expiry)
+        .and_then(|renew_at| renew_at.duration_since(now).ok());
+    if lifetime.is_none_or(|lifetime| lifetime < MIN_LIFETIME) {
+        return Err(RotationError::RenewalTooSoon(MIN_LIFETIME));
+    }
+    let signing_key = rustls::sign::any_supported_type(&private_key)?;
+
+    Ok(Current {
+        key: Arc::new(CertifiedKey::new(vec![certificate], signing_key)),
+        generated_at: now,
+        not_after,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Waits until the rotation thread replaces `certificate`, returning the new one.
+    fn wait_for_rotation(
+        resolver: &RotatingCertResolver,
+        certificate: &rustls::Certificate,
+    ) -> rustls::Certificate {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let current = resolver.certificate();
+            if current != *certificate {
+                return current;
+            }
+            assert!(Instant::now() < deadline, "certificate was not rotated");
+            thread::sleep(MIN_WAIT);
+        }
+    }
+
+    fn resolver(schedule: RotationSchedule) -> RotatingCertResolver {
+        let identity = Keypair::generate_ed25519();
+        RotatingCertResolver::new(&identity, CertificateParams::default(), schedule).unwrap()
+    }
+
+    fn new_resolver(
+        params: CertificateParams,
+        schedule: RotationSchedule,
+    ) -> Result<RotatingCertResolver, RotationError> {
+        RotatingCertResolver::new(&Keypair::generate_ed25519(), params, schedule)
+    }
+
+    #[test]
+    fn keeps_certificate_until_due() {
+        let resolver = resolver(RotationSchedule::new().with_interval(Duration::from_secs(3600)));
+        let certificate = resolver.certificate();
+
+        assert_eq!(resolver.resolve().cert[0], certificate);
+        assert_eq!(resolver.certificate(), certificate);
+    }
+
+    #[test]
+    fn rotates_after_interval() {
+        let resolver = resolver(RotationSchedule::new().with_interval(MIN_LIFETIME));
+        let certificate = resolver.certificate();
+
+        assert_ne!(wait_for_rotation(&resolver, &certificate), certificate);
+    }
+
+    #[test]
+    fn rotates_before_expiry() {
+        let schedule = RotationSchedule::new()
+            .with_validity(Duration::from_secs(3600))
+            // Certificates store their validity in whole seconds, so leave a few of them.
+            .with_renew_before_expiry(Duration::from_secs(3597));
+        let resolver = resolver(schedule);
+        let certificate = resolver.certificate();
+
+        let rotated = wait_for_rotation(&resolver, &certificate);
+
+        certificate::parse(&rotated).expect("rotated certificate to be valid");
+    }
+
+    #[test]
+    fn rotation_thread_stops_with_resolver() {
+        let resolver = resolver(RotationSchedule::new().with_interval(MIN_LIFETIME));
+        let inner = Arc::downgrade(&resolver.inner);
+
+        drop(resolver);
+
+        // The thread only holds on to the resolver while rotating.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while inner.upgrade().is_some() {
+            assert!(
+                Instant::now() < deadline,
+                "rotation thread kept the resolver alive"
+            );
+            thread::sleep(MIN_WAIT);
+        }
+    }
+
+    #[test]
+    fn rotate_replaces_certificate() {
+        let resolver = resolver(RotationSchedule::new());
+        let certificate = resolver.certificate();
+
+        resolver.rotate().unwrap();
+
+        assert_ne!(resolver.certificate(), certificate);
+        assert_eq!(resolver.resolve().cert[0], resolver.certificate());
+    }
+
+    #[test]
+    fn rejects_zero_interval() {
+        let schedule = RotationSchedule::new().with_interval(Duration::ZERO);
+
+        assert!(matches!(
+            new_resolver(CertificateParams::default(), schedule),
+            Err(RotationError::IntervalTooShort(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_renewal_margin_covering_validity() {
+        let schedule = RotationSchedule::new()
+            .with_validity(Duration::from_secs(3600))
+            .with_renew_before_expiry(Duration::from_secs(3600));
+
+        assert!(matches!(
+            new_resolver(CertificateParams::default(), schedule),
+            Err(RotationError::RenewalTooSoon(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_renewal_margin_covering_validity_of_params() {
+        let now = SystemTime::now();
+        let params =
+            CertificateParams::default().with_validity(now, now + Duration::from_secs(3600));
+        let schedule = RotationSchedule::new().with_renew_before_expiry(Duration::from_secs(7200));
+
+        assert!(matches!(
+            new_resolver(params, schedule),
+            Err(RotationError::RenewalTooSoon(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_expired_params() {
+        let now = SystemTime::now();
+        let params = CertificateParams::default().with_validity(
+            now - Duration::from_secs(7200),
+            now - Duration::from_secs(3600),
+        );
+
+        assert!(matches!(
+            new_resolver(params, RotationSchedule::new()),
+            Err(RotationError::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_validity_beyond_system_time() {
+        let schedule = RotationSchedule::new().with_validity(Duration::MAX);
+
+        assert!(matches!(
+            new_resolver(CertificateParams::default(), schedule),
+            Err(RotationError::ValidityOutOfRange)
+        ));
+    }
+}
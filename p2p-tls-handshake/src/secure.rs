@@ -2,6 +2,8 @@
 //! or `OutboundSecurityUpgrade::secure_outbound` method is called and a [`Future`] that performs a
 //! handshake is returned.
 
+use std::error::Error;
+use std::io;
 use std::iter::IntoIterator;
 
 use crate::error::UpgradeError;
@@ -12,14 +14,14 @@ use libp2p_core::{ConnectedPoint, Negotiated, UpgradeInfo};
 
 use libp2p_core::multiaddr::Protocol;
 use libp2p_identity::PeerId;
-use multistream_select::Version;
+use multistream_select::{NegotiationError, Version};
 
 /// An inbound or outbound security upgrade.
-pub(crate) type EitherSecurityFuture<C, U> =
+pub type EitherSecurityFuture<C, U> =
     Either<InboundSecurityFuture<C, U>, OutboundSecurityFuture<C, U>>;
 
 /// An inbound security upgrade represented by an owned trait object `Future`.
-pub(crate) type InboundSecurityFuture<C, U> = BoxFuture<
+pub type InboundSecurityFuture<C, U> = BoxFuture<
     'static,
     Result<
         (PeerId, <U as InboundSecurityUpgrade<Negotiated<C>>>::Output),
@@ -28,7 +30,7 @@ pub(crate) type InboundSecurityFuture<C, U> = BoxFuture<
 >;
 
 /// An outbound security upgrade represented by an owned trait object `Future`.
-pub(crate) type OutboundSecurityFuture<C, U> = BoxFuture<
+pub type OutboundSecurityFuture<C, U> = BoxFuture<
     'static,
     Result<
         (
@@ -40,12 +42,11 @@ pub(crate) type OutboundSecurityFuture<C, U> = BoxFuture<
 >;
 
 /// Applies a security upgrade to the inbound and outbound direction of a connection or substream.
-pub(crate) fn secure<C, U>(
-    conn: C,
-    up: U,
-    cp: ConnectedPoint,
-    v: Version,
-) -> EitherSecurityFuture<C, U>
+///
+/// With [`Version::V1Lazy`], a dialer proposing a single protocol starts the handshake without
+/// waiting for the listener to confirm it, so that its first message is sent along with the
+/// proposal. A refused proposal still fails with [`UpgradeError::Select`].
+pub fn secure<C, U>(conn: C, up: U, cp: ConnectedPoint, v: Version) -> EitherSecurityFuture<C, U>
 where
     C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     U: InboundSecurityUpgrade<Negotiated<C>>
@@ -55,6 +56,7 @@ where
     <U as UpgradeInfo>::Info: Send,
     <U as InboundSecurityUpgrade<Negotiated<C>>>::Future: Send,
     <U as OutboundSecurityUpgrade<Negotiated<C>>>::Future: Send,
+    <U as OutboundSecurityUpgrade<Negotiated<C>>>::Error: Error + 'static,
     <<U as UpgradeInfo>::InfoIter as IntoIterator>::IntoIter: Send,
 {
     match cp {
@@ -75,6 +77,10 @@ where
                         tracing::trace!(up=%name, "Secured outbound stream");
                         Ok(x)
                     }
+                    Err(e) if refused(&e) => {
+                        tracing::trace!(up=%name, "Listener refused the optimistic proposal");
+                        Err(UpgradeError::Select(NegotiationError::Failed))
+                    }
                     Err(e) => {
                         tracing::trace!(up=%name, "Failed to secure outbound stream");
                         Err(UpgradeError::Apply(e))
@@ -103,3 +109,21 @@ where
         ),
     }
 }
+
+/// Whether `error` stems from the listener refusing the protocol that a lazy dialer optimistically
+/// settled on, which the negotiated stream reports as I/O error once the handshake reads from it.
+fn refused(error: &(dyn Error + 'static)) -> bool {
+    let mut error = Some(error);
+    while let Some(e) = error {
+        let negotiation = e
+            .downcast_ref::<io::Error>()
+            .and_then(io::Error::get_ref)
+            .and_then(|e| e.downcast_ref::<NegotiationError>());
+        if matches!(negotiation, Some(NegotiationError::Failed)) {
+            return true;
+        }
+        error = e.source();
+    }
+
+    false
+}
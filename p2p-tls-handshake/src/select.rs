@@ -0,0 +1,139 @@
+//! Selection between two security upgrades
+//!
+//! A [`SelectSecurityUpgrade`] offers the protocols of two security upgrades in one negotiation,
+//! e.g. TLS first and another protocol second while peers migrate from one to the other.
+
+use crate::upgrade::{InboundSecurityUpgrade, OutboundSecurityUpgrade};
+use either::Either;
+use futures::{future, Future, TryFuture};
+use libp2p_core::UpgradeInfo;
+use libp2p_identity::PeerId;
+use pin_project::pin_project;
+use std::{
+    iter::{Chain, Map},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A security upgrade offering the protocols of both `A` and `B`, those of `A` first.
+///
+/// The connection is secured by whichever upgrade the negotiated protocol belongs to, so its
+/// output is a [`future::Either`] of both outputs.
+#[derive(Debug, Clone)]
+pub struct SelectSecurityUpgrade<A, B>(A, B);
+
+impl<A, B> SelectSecurityUpgrade<A, B> {
+    /// Combines two security upgrades, preferring the protocols of `a` over those of `b`.
+    pub fn new(a: A, b: B) -> Self {
+        Self(a, b)
+    }
+}
+
+impl<A, B> UpgradeInfo for SelectSecurityUpgrade<A, B>
+where
+    A: UpgradeInfo,
+    B: UpgradeInfo,
+{
+    type Info = Either<A::Info, B::Info>;
+    type InfoIter = Chain<
+        Map<<A::InfoIter as IntoIterator>::IntoIter, fn(A::Info) -> Self::Info>,
+        Map<<B::InfoIter as IntoIterator>::IntoIter, fn(B::Info) -> Self::Info>,
+    >;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let a = self
+            .0
+            .protocol_info()
+            .into_iter()
+            .map(Either::Left as fn(A::Info) -> _);
+        let b = self
+            .1
+            .protocol_info()
+            .into_iter()
+            .map(Either::Right as fn(B::Info) -> _);
+
+        a.chain(b)
+    }
+}
+
+impl<C, A, B> InboundSecurityUpgrade<C> for SelectSecurityUpgrade<A, B>
+where
+    A: InboundSecurityUpgrade<C>,
+    B: InboundSecurityUpgrade<C>,
+{
+    type Output = future::Either<A::Output, B::Output>;
+    type Error = Either<A::Error, B::Error>;
+    type Future = SelectSecurityFuture<A::Future, B::Future>;
+
+    fn secure_inbound(self, socket: C, info: Self::Info) -> Self::Future {
+        match info {
+            Either::Left(info) => SelectSecurityFuture::First(self.0.secure_inbound(socket, info)),
+            Either::Right(info) => {
+                SelectSecurityFuture::Second(self.1.secure_inbound(socket, info))
+            }
+        }
+    }
+
+    fn early_muxer(output: &Self::Output) -> Option<&str> {
+        match output {
+            future::Either::Left(output) => A::early_muxer(output),
+            future::Either::Right(output) => B::early_muxer(output),
+        }
+    }
+}
+
+impl<C, A, B> OutboundSecurityUpgrade<C> for SelectSecurityUpgrade<A, B>
+where
+    A: OutboundSecurityUpgrade<C>,
+    B: OutboundSecurityUpgrade<C>,
+{
+    type Output = future::Either<A::Output, B::Output>;
+    type Error = Either<A::Error, B::Error>;
+    type Future = SelectSecurityFuture<A::Future, B::Future>;
+
+    fn secure_outbound(self, socket: C, info: Self::Info, peer_id: Option<PeerId>) -> Self::Future {
+        match info {
+            Either::Left(info) => {
+                SelectSecurityFuture::First(self.0.secure_outbound(socket, info, peer_id))
+            }
+            Either::Right(info) => {
+                SelectSecurityFuture::Second(self.1.secure_outbound(socket, info, peer_id))
+            }
+        }
+    }
+
+    fn early_muxer(output: &Self::Output) -> Option<&str> {
+        match output {
+            future::Either::Left(output) => A::early_muxer(output),
+            future::Either::Right(output) => B::early_muxer(output),
+        }
+    }
+}
+
+/// The handshake of a [`SelectSecurityUpgrade`], performed by either of its upgrades.
+#[pin_project(project = SelectSecurityFutureProj)]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub enum SelectSecurityFuture<A, B> {
+    First(#[pin] A),
+    Second(#[pin] B),
+}
+
+impl<A, B, DA, DB> Future for SelectSecurityFuture<A, B>
+where
+    A: TryFuture<Ok = (PeerId, DA)>,
+    B: TryFuture<Ok = (PeerId, DB)>,
+{
+    type Output = Result<(PeerId, future::Either<DA, DB>), Either<A::Error, B::Error>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project() {
+            SelectSecurityFutureProj::First(a) => TryFuture::try_poll(a, cx)
+                .map_ok(|(peer_id, output)| (peer_id, future::Either::Left(output)))
+                .map_err(Either::Left),
+            SelectSecurityFutureProj::Second(b) => TryFuture::try_poll(b, cx)
+                .map_ok(|(peer_id, output)| (peer_id, future::Either::Right(output)))
+                .map_err(Either::Right),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+//! Persistent storage of certificates
+//!
+//! A certificate generated by [`generate`](crate::generate) is bound to the host identity by the
+//! libp2p extension. Storing it together with its private key keeps the certificate fingerprint
+//! stable across restarts, which makes it usable for monitoring and pinning.
+
+use crate::certificate::{self, CertificateParams, GenError};
+use crate::error::StoreError;
+use libp2p_identity::Keypair;
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const CERTIFICATE_TAG: &str = "CERTIFICATE";
+const PRIVATE_KEY_TAG: &str = "PRIVATE KEY";
+/// How many random names are tried for a temporary file before giving up.
+const TEMP_FILE_ATTEMPTS: usize = 8;
+
+/// A certificate and the PKCS#8 private key of its keypair, checked to belong to a host identity.
+#[derive(Clone, Debug)]
+pub struct StoredCertificate {
+    certificate: rustls::Certificate,
+    private_key: rustls::PrivateKey,
+}
+
+impl StoredCertificate {
+    /// Generates a new certificate for `identity` according to `params`.
+    pub fn generate(identity: &Keypair, params: &CertificateParams) -> Result<Self, GenError> {
+        let (certificate, private_key) = certificate::generate_with_params(identity, params)?;
+
+        Ok(Self {
+            certificate,
+            private_key,
+        })
+    }
+
+    /// Builds a stored certificate from its DER encoding and the PKCS#8 DER encoding of its
+    /// private key.
+    ///
+    /// Fails if the certificate is not a valid libp2p certificate of `identity`, or if the private
+    /// key does not belong to the certificate.
+    pub fn from_der(
+        identity: &Keypair,
+        certificate: Vec<u8>,
+        private_key: Vec<u8>,
+    ) -> Result<Self, StoreError> {
+        let stored = Self {
+            certificate: rustls::Certificate(certificate),
+            private_key: rustls::PrivateKey(private_key),
+        };
+        stored.verify(identity)?;
+
+        Ok(stored)
+    }
+
+    /// Decodes a stored certificate from a PEM document with a `CERTIFICATE` and a `PRIVATE KEY`
+    /// block, as produced by [`to_pem`](Self::to_pem).
+    pub fn from_pem(identity: &Keypair, pem: &[u8]) -> Result<Self, StoreError> {
+        let mut certificate = None;
+        let mut private_key = None;
+        for block in pem::parse_many(pem)? {
+            match block.tag() {
+                CERTIFICATE_TAG if certificate.is_none() => {
+                    certificate = Some(block.into_contents())
+                }
+                PRIVATE_KEY_TAG if private_key.is_none() => {
+                    private_key = Some(block.into_contents())
+                }
+                tag => return Err(StoreError::UnexpectedPemBlock(tag.to_owned())),
+            }
+        }
+
+        Self::from_der(
+            identity,
+            certificate.ok_or(StoreError::MissingPemBlock(CERTIFICATE_TAG))?,
+            private_key.ok_or(StoreError::MissingPemBlock(PRIVATE_KEY_TAG))?,
+        )
+    }
+
+    /// Encodes the certificate and its private key as a PEM document.
+    pub fn to_pem(&self) -> String {
+        pem::encode_many(&[
+            pem::Pem::new(CERTIFICATE_TAG, self.certificate.0.clone()),
+            pem::Pem::new(PRIVATE_KEY_TAG, self.private_key.0.clone()),
+        ])
+    }
+
+    /// Loads a stored certificate from a PEM file written by [`save_pem`](Self::save_pem).
+    pub fn load_pem(identity: &Keypair, path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        Self::from_pem(identity, &fs::read(path)?)
+    }
+
+    /// Writes the certificate and its private key to a single PEM file.
+    ///
+    /// The file is only readable by its owner, and replaced atomically if it exists.
+    pub fn save_pem(&self, path: impl AsRef<Path>) -> Result<(), StoreError> {
+        write_private(path.as_ref(), self.to_pem().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Loads a stored certificate from the DER files written by [`save_der`](Self::save_der).
+    pub fn load_der(
+        identity: &Keypair,
+        certificate_path: impl AsRef<Path>,
+        private_key_path: impl AsRef<Path>,
+    ) -> Result<Self, StoreError> {
+        Self::from_der(
+            identity,
+            fs::read(certificate_path)?,
+            fs::read(private_key_path)?,
+        )
+    }
+
+    /// Writes the certificate and its private key to two DER files.
+    ///
+    /// The private key file is only readable by its owner, and replaced atomically if it exists.
+    pub fn save_der(
+        &self,
+        certificate_path: impl AsRef<Path>,
+        private_key_path: impl AsRef<Path>,
+    ) -> Result<(), StoreError> {
+        fs::write(certificate_path, &self.certificate.0)?;
+        write_private(private_key_path.as_ref(), &self.private_key.0)?;
+
+        Ok(())
+    }
+
+    /// The certificate.
+    pub fn certificate(&self) -> &rustls::Certificate {
+        &self.certificate
+    }
+
+    /// The PKCS#8 private key of the certificate keypair.
+    pub fn private_key(&self) -> &rustls::PrivateKey {
+        &self.private_key
+    }
+
+    /// Splits into the certificate and its private key.
+    pub fn into_parts(self) -> (rustls::Certificate, rustls::PrivateKey) {
+        (self.certificate, self.private_key)
+    }
+
+    fn verify(&self, identity: &Keypair) -> Result<(), StoreError> {
+        let certificate = certificate::parse(&self.certificate)?;
+
+        let peer_id = certificate.peer_id();
+        if peer_id != identity.public().to_peer_id() {
+            return Err(StoreError::IdentityMismatch(peer_id));
+        }
+
+        let key_pair = rcgen::KeyPair::from_der(&self.private_key.0)
+            .map_err(|e| StoreError::BadPrivateKey(e.into()))?;
+        if key_pair.public_key_der() != certificate.subject_public_key() {
+            return Err(StoreError::PrivateKeyMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `contents` to a temporary file only readable by its owner, and renames it to `path`,
+/// so that the private key is never exposed, nor left half-written.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    // The permissions only apply to new files, so never reuse an existing one.
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let (temp_path, mut file) = create_temp_file(path, &options)?;
+    let written = file.write_all(contents).and_then(|()| file.sync_all());
+    drop(file);
+    match written.and_then(|()| fs::rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Creates a file with a random name next to `path`, so that it is renamed within the same file
+/// system.
+fn create_temp_file(path: &Path, options: &fs::OpenOptions) -> io::Result<(PathBuf, fs::File)> {
+    let rng = ring::rand::SystemRandom::new();
+    let mut attempts = 0;
+    loop {
+        let suffix: [u8; 8] = ring::rand::generate(&rng)
+            .map_err(|_| io::Error::other("Failed to generate temporary file name"))?
+            .expose();
+        let mut temp_path = OsString::from(path);
+        temp_path.push(format!(".{:016x}.tmp", u64::from_le_bytes(suffix)));
+        let temp_path = PathBuf::from(temp_path);
+
+        match options.open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                attempts += 1;
+                if attempts == TEMP_FILE_ATTEMPTS {
+                    return Err(e);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::certificate::CertificateAlgorithm;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("p2p-tls-handshake-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn pem_round_trip() {
+        let identity = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+
+        let loaded = StoredCertificate::from_pem(&identity, stored.to_pem().as_bytes()).unwrap();
+
+        assert_eq!(loaded.certificate(), stored.certificate());
+        assert_eq!(loaded.private_key(), stored.private_key());
+    }
+
+    #[test]
+    fn pem_file_round_trip() {
+        let identity = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+        let path = temp_path("pem-file-round-trip.pem");
+
+        stored.save_pem(&path).unwrap();
+        let loaded = StoredCertificate::load_pem(&identity, &path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap().certificate(), stored.certificate());
+    }
+
+    #[test]
+    fn der_file_round_trip() {
+        let identity = Keypair::generate_ed25519();
+        let params = CertificateParams::new().with_algorithm(CertificateAlgorithm::Ed25519);
+        let stored = StoredCertificate::generate(&identity, &params).unwrap();
+        let certificate_path = temp_path("der-file-round-trip.crt");
+        let private_key_path = temp_path("der-file-round-trip.key");
+
+        stored
+            .save_der(&certificate_path, &private_key_path)
+            .unwrap();
+        let loaded = StoredCertificate::load_der(&identity, &certificate_path, &private_key_path);
+        fs::remove_file(&certificate_path).unwrap();
+        fs::remove_file(&private_key_path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.certificate(), stored.certificate());
+        assert_eq!(loaded.private_key(), stored.private_key());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_key_files_are_only_readable_by_their_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let identity = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+        let pem_path = temp_path("private-key-permissions.pem");
+        let certificate_path = temp_path("private-key-permissions.crt");
+        let private_key_path = temp_path("private-key-permissions.key");
+
+        stored.save_pem(&pem_path).unwrap();
+        stored
+            .save_der(&certificate_path, &private_key_path)
+            .unwrap();
+        let modes = [&pem_path, &private_key_path]
+            .map(|path| fs::metadata(path).unwrap().permissions().mode() & 0o777);
+        for path in [&pem_path, &certificate_path, &private_key_path] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(modes, [0o600, 0o600]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_key_is_never_written_to_existing_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let identity = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+        let dir = temp_path("existing-temp-file");
+        fs::create_dir(&dir).unwrap();
+        let certificate_path = dir.join("certificate.crt");
+        let private_key_path = dir.join("private.key");
+        // A readable file left behind, or planted, at a predictable temporary path.
+        let planted_path = dir.join("private.key.tmp");
+        fs::write(&planted_path, b"").unwrap();
+        fs::set_permissions(&planted_path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let saved = stored.save_der(&certificate_path, &private_key_path);
+        let planted = fs::read(&planted_path).unwrap();
+        let mut entries = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        entries.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        saved.unwrap();
+        assert!(planted.is_empty());
+        assert_eq!(
+            entries,
+            ["certificate.crt", "private.key", "private.key.tmp"].map(OsString::from)
+        );
+    }
+
+    #[test]
+    fn load_rejects_other_identity() {
+        let identity = Keypair::generate_ed25519();
+        let other = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+
+        let error = StoredCertificate::from_pem(&other, stored.to_pem().as_bytes()).unwrap_err();
+
+        assert!(matches!(
+            error,
+            StoreError::IdentityMismatch(peer_id) if peer_id == identity.public().to_peer_id()
+        ));
+    }
+
+    #[test]
+    fn load_rejects_other_private_key() {
+        let identity = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+        let other = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+
+        let error = StoredCertificate::from_der(
+            &identity,
+            stored.certificate().0.clone(),
+            other.private_key().0.clone(),
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, StoreError::PrivateKeyMismatch));
+    }
+
+    #[test]
+    fn load_rejects_incomplete_pem() {
+        let identity = Keypair::generate_ed25519();
+        let stored = StoredCertificate::generate(&identity, &CertificateParams::default()).unwrap();
+        let pem = pem::encode(&pem::Pem::new(
+            CERTIFICATE_TAG,
+            stored.certificate().0.clone(),
+        ));
+
+        let error = StoredCertificate::from_pem(&identity, pem.as_bytes()).unwrap_err();
+
+        assert!(matches!(
+            error,
+            StoreError::MissingPemBlock(PRIVATE_KEY_TAG)
+        ));
+    }
+}
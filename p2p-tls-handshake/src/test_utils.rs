@@ -0,0 +1,40 @@
+//! Helpers shared by the unit tests of the crate
+
+use futures::future;
+use libp2p_core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade};
+use libp2p_core::UpgradeInfo;
+use std::convert::Infallible;
+use std::iter;
+
+/// An upgrade that passes the connection through, once negotiated.
+#[derive(Clone)]
+pub(crate) struct Passthrough;
+
+impl UpgradeInfo for Passthrough {
+    type Info = &'static str;
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once("/passthrough/1.0.0")
+    }
+}
+
+impl<C> InboundConnectionUpgrade<C> for Passthrough {
+    type Output = C;
+    type Error = Infallible;
+    type Future = future::Ready<Result<C, Infallible>>;
+
+    fn upgrade_inbound(self, socket: C, _: Self::Info) -> Self::Future {
+        future::ok(socket)
+    }
+}
+
+impl<C> OutboundConnectionUpgrade<C> for Passthrough {
+    type Output = C;
+    type Error = Infallible;
+    type Future = future::Ready<Result<C, Infallible>>;
+
+    fn upgrade_outbound(self, socket: C, _: Self::Info) -> Self::Future {
+        future::ok(socket)
+    }
+}
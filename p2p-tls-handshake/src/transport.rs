@@ -1,4 +1,33 @@
 //! Configuration of transport protocol upgrades.
+//!
+//! The [`Builder`] drives a base [`Transport`] through the security upgrade
+//! (see [`InboundSecurityUpgrade`]/[`OutboundSecurityUpgrade`], or a classic connection
+//! upgrade through [`Builder::authenticate`]), any number of additional upgrades and finally
+//! the negotiation of a stream multiplexer.
+//!
+//! ```
+//! use libp2p_core::muxing::StreamMuxerBox;
+//! use libp2p_identity::{Keypair, PeerId};
+//! use p2p_tls_handshake::{Boxed, Builder, Config, Version};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let keypair = Keypair::generate_ed25519();
+//! let tcp = libp2p_tcp::tokio::Transport::new(libp2p_tcp::Config::default());
+//!
+//! let transport: Boxed<(PeerId, StreamMuxerBox)> = Builder::new(tcp, Version::V1)
+//!     .authenticate2(Config::new(&keypair)?)
+//!     .multiplex(libp2p_yamux::Config::default())
+//!     .boxed();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The authenticated transport outputs an [`AuthenticatedPeer`] rather than a bare [`PeerId`],
+//! so that [`Authenticated::multiplex_early`] learns about the multiplexer agreed on during the
+//! security handshake. Transports built with [`Authenticated::apply`] and
+//! [`Authenticated::multiplex`] are unaffected, and still output `(PeerId, M)` once multiplexed.
+//! Code that handles the authenticated output itself, e.g. in a [`Transport::map`], gets the
+//! [`PeerId`] from [`AuthenticatedPeer::peer_id`].
 
 use futures::{future, ready, AsyncRead, AsyncWrite, Future, TryFuture};
 use libp2p_core::{
@@ -11,6 +40,7 @@ use libp2p_core::{
     ConnectedPoint, Negotiated, StreamMuxer, Transport, UpgradeInfo,
 };
 use libp2p_identity::PeerId;
+use multistream_select::NegotiationError;
 use std::{
     error::Error,
     fmt,
@@ -32,14 +62,14 @@ use crate::{
 ///
 /// The upgrade process is defined by the following stages:
 ///
-///    [`authenticate`](Builder::authenticate)`{1}`
+///    [`authenticate`](Builder::authenticate) or [`authenticate2`](Builder::authenticate2)`{1}`
 /// -> [`apply`](Authenticated::apply)`{*}`
-/// -> [`multiplex`](Authenticated::multiplex)`{1}`
+/// -> [`multiplex`](Authenticated::multiplex) or [`multiplex_early`](Authenticated::multiplex_early)`{1}`
 ///
 /// It thus enforces the following invariants on every transport
 /// obtained from [`multiplex`](Authenticated::multiplex):
 ///
-///   1. The transport must be [authenticated](Builder::authenticate)
+///   1. The transport must be [authenticated](Builder::authenticate2)
 ///      and [multiplexed](Authenticated::multiplex).
 ///   2. Authentication must precede the negotiation of a multiplexer.
 ///   3. Applying a multiplexer is the last step in the upgrade process.
@@ -62,6 +92,45 @@ where
         Builder { inner, version }
     }
 
+    /// Upgrades the transport to perform authentication of the remote.
+    ///
+    /// The supplied upgrade receives the I/O resource `C` and must
+    /// produce a pair `(PeerId, D)`, where `D` is a new I/O resource.
+    /// The upgrade must thus at a minimum identify the remote, which typically
+    /// involves the use of a cryptographic authentication protocol in the
+    /// context of establishing a secure channel.
+    ///
+    /// This accepts the security upgrades of rust-libp2p, such as noise, which implement
+    /// `InboundConnectionUpgrade`/`OutboundConnectionUpgrade`. Unlike with
+    /// [`authenticate2`](Builder::authenticate2), the [`PeerId`] of the dialed address is not
+    /// checked during the handshake.
+    ///
+    /// ## Transitions
+    ///
+    ///   * I/O upgrade: `C -> (PeerId, D)`.
+    ///   * Transport output: `C -> (AuthenticatedPeer, D)`
+    #[allow(clippy::type_complexity)]
+    pub fn authenticate<C, D, U, E>(
+        self,
+        upgrade: U,
+    ) -> Authenticated<AndThen<T, impl FnOnce(C, ConnectedPoint) -> Authenticate<C, U> + Clone>>
+    where
+        T: Transport<Output = C>,
+        C: AsyncRead + AsyncWrite + Unpin,
+        D: AsyncRead + AsyncWrite + Unpin,
+        U: InboundConnectionUpgrade<Negotiated<C>, Output = (PeerId, D), Error = E>,
+        U: OutboundConnectionUpgrade<Negotiated<C>, Output = (PeerId, D), Error = E> + Clone,
+        E: Error + 'static,
+    {
+        let version = self.version;
+        Authenticated(Builder::new(
+            self.inner.and_then(move |conn, endpoint| Authenticate {
+                inner: crate::apply(conn, upgrade, endpoint, version),
+            }),
+            version,
+        ))
+    }
+
     /// Upgrades the transport to perform authentication of the remote
     ///
     /// The supplied upgrade receives the I/O resource `C` and must
@@ -73,13 +142,15 @@ where
     /// ## Transitions
     ///
     ///   * I/O upgrade: `C -> (PeerId, D)`.
-    ///   * Transport output: `C -> (PeerId, D)`
+    ///   * Transport output: `C -> (AuthenticatedPeer, D)`
     ///
-    /// ## Alternative state
+    /// ## Security upgrade
     ///
-    /// This function is an alternative code path to [`Builder::authenticate`]
-    /// given the supplied upgrade implements `InboundConnectionUpgrade`/`OutboundSecurityUpgrade`
-    /// instead of `InboundConnectionUpgrade`/`OutboundConnectionUpgrade`.
+    /// The supplied upgrade implements [`InboundSecurityUpgrade`]/[`OutboundSecurityUpgrade`]
+    /// instead of `InboundConnectionUpgrade`/`OutboundConnectionUpgrade`. On outbound
+    /// connections it is handed the [`PeerId`] of the dialed address, if any, so the
+    /// handshake itself can be aborted on a mismatch.
+    #[allow(clippy::type_complexity)]
     pub fn authenticate2<C, D, U, E>(
         self,
         upgrade: U,
@@ -88,11 +159,8 @@ where
         T: Transport<Output = C>,
         C: AsyncRead + AsyncWrite + Unpin + Send + 'static,
         D: AsyncRead + AsyncWrite + Unpin,
-        U: InboundSecurityUpgrade<Negotiated<C>, Output = (PeerId, D), Error = E> + Send + 'static,
-        U: OutboundSecurityUpgrade<Negotiated<C>, Output = (PeerId, D), Error = E>
-            + Clone
-            + Send
-            + 'static,
+        U: InboundSecurityUpgrade<Negotiated<C>, Output = D, Error = E> + Send + 'static,
+        U: OutboundSecurityUpgrade<Negotiated<C>, Output = D, Error = E> + Clone + Send + 'static,
         E: Error + 'static,
         <U as UpgradeInfo>::Info: Send,
         <U as InboundSecurityUpgrade<Negotiated<C>>>::Future: Send,
@@ -109,6 +177,35 @@ where
     }
 }
 
+/// An upgrade that authenticates the remote peer, typically
+/// in the context of negotiating a secure channel.
+///
+/// Configured through [`Builder::authenticate`].
+#[pin_project::pin_project]
+pub struct Authenticate<C, U>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: InboundConnectionUpgrade<Negotiated<C>> + OutboundConnectionUpgrade<Negotiated<C>>,
+{
+    #[pin]
+    inner: EitherUpgrade<C, U>,
+}
+
+impl<C, U, D, E> Future for Authenticate<C, U>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: InboundConnectionUpgrade<Negotiated<C>, Output = (PeerId, D), Error = E>,
+    U: OutboundConnectionUpgrade<Negotiated<C>, Output = (PeerId, D), Error = E>,
+{
+    type Output = Result<(AuthenticatedPeer, D), UpgradeError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let (peer_id, d) = ready!(Future::poll(this.inner, cx))?;
+        Poll::Ready(Ok((AuthenticatedPeer::new(peer_id, None), d)))
+    }
+}
+
 /// An upgrade that authenticates the remote peer, typically
 /// in the context of negotiating a secure channel.
 ///
@@ -123,21 +220,60 @@ where
     inner: EitherSecurityFuture<C, U>,
 }
 
-impl<C, U> Future for Authenticate2<C, U>
+impl<C, U, D, E> Future for Authenticate2<C, U>
 where
     C: AsyncRead + AsyncWrite + Unpin,
-    U: InboundSecurityUpgrade<Negotiated<C>>
-        + OutboundSecurityUpgrade<
-            Negotiated<C>,
-            Output = <U as InboundSecurityUpgrade<Negotiated<C>>>::Output,
-            Error = <U as InboundSecurityUpgrade<Negotiated<C>>>::Error,
-        >,
+    U: InboundSecurityUpgrade<Negotiated<C>, Output = D, Error = E>,
+    U: OutboundSecurityUpgrade<Negotiated<C>, Output = D, Error = E>,
 {
-    type Output = <EitherSecurityFuture<C, U> as Future>::Output;
+    type Output = Result<(AuthenticatedPeer, D), UpgradeError<E>>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        Future::poll(this.inner, cx)
+        let (peer_id, d, early_muxer) = match this.inner.as_pin_mut() {
+            future::Either::Left(inbound) => {
+                let (peer_id, d) = ready!(inbound.poll(cx))?;
+                let early_muxer =
+                    <U as InboundSecurityUpgrade<Negotiated<C>>>::early_muxer(&d).map(Into::into);
+                (peer_id, d, early_muxer)
+            }
+            future::Either::Right(outbound) => {
+                let (peer_id, d) = ready!(outbound.poll(cx))?;
+                let early_muxer =
+                    <U as OutboundSecurityUpgrade<Negotiated<C>>>::early_muxer(&d).map(Into::into);
+                (peer_id, d, early_muxer)
+            }
+        };
+        Poll::Ready(Ok((AuthenticatedPeer::new(peer_id, early_muxer), d)))
+    }
+}
+
+/// The remote peer of a connection authenticated by [`Builder::authenticate`] or
+/// [`Builder::authenticate2`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedPeer {
+    peer_id: PeerId,
+    early_muxer: Option<String>,
+}
+
+impl AuthenticatedPeer {
+    fn new(peer_id: PeerId, early_muxer: Option<String>) -> Self {
+        Self {
+            peer_id,
+            early_muxer,
+        }
+    }
+
+    /// The [`PeerId`] of the remote peer.
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// The stream multiplexer agreed on during the security handshake, if any.
+    ///
+    /// See [`InboundSecurityUpgrade::early_muxer`].
+    pub fn early_muxer(&self) -> Option<&str> {
+        self.early_muxer.as_deref()
     }
 }
 
@@ -166,19 +302,130 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        let m = match ready!(Future::poll(this.upgrade, cx)) {
-            Ok(m) => m,
-            Err(err) => return Poll::Ready(Err(err)),
+        let m = ready!(Future::poll(this.upgrade, cx))?;
+        match this.peer_id.take() {
+            Some(i) => Poll::Ready(Ok((i, m))),
+            None => Poll::Ready(Err(UpgradeError::PolledAfterCompletion)),
+        }
+    }
+}
+
+/// An upgrade that applies the (sub)stream multiplexer agreed on during the security handshake,
+/// or negotiates one on top of an authenticated transport otherwise.
+///
+/// Configured through [`Authenticated::multiplex_early`].
+#[pin_project::pin_project]
+pub struct MultiplexEarly<C, U>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: InboundConnectionUpgrade<Negotiated<C>> + OutboundConnectionUpgrade<Negotiated<C>>,
+    U: InboundConnectionUpgrade<C> + OutboundConnectionUpgrade<C>,
+{
+    peer_id: Option<PeerId>,
+    #[pin]
+    upgrade: MultiplexUpgrade<C, U>,
+}
+
+impl<C, U> MultiplexEarly<C, U>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: InboundConnectionUpgrade<Negotiated<C>> + OutboundConnectionUpgrade<Negotiated<C>>,
+    U: InboundConnectionUpgrade<C> + OutboundConnectionUpgrade<C>,
+{
+    fn new(
+        peer: AuthenticatedPeer,
+        conn: C,
+        upgrade: U,
+        endpoint: ConnectedPoint,
+        version: upgrade::Version,
+    ) -> Self {
+        let Some(early_muxer) = peer.early_muxer() else {
+            return MultiplexEarly {
+                peer_id: Some(peer.peer_id),
+                upgrade: MultiplexUpgrade::Negotiate(crate::apply(
+                    conn, upgrade, endpoint, version,
+                )),
+            };
+        };
+
+        // Both peers agreed on the multiplexer already, it is upgraded to without negotiation.
+        let info = upgrade
+            .protocol_info()
+            .into_iter()
+            .find(|info| info.as_ref() == early_muxer);
+        let upgrade = match (info, endpoint) {
+            (Some(info), ConnectedPoint::Dialer { role_override, .. })
+                if role_override.is_dialer() =>
+            {
+                MultiplexUpgrade::Outbound(upgrade.upgrade_outbound(conn, info))
+            }
+            (Some(info), _) => MultiplexUpgrade::Inbound(upgrade.upgrade_inbound(conn, info)),
+            (None, _) => {
+                tracing::debug!(
+                    protocol = %early_muxer,
+                    "Multiplexer agreed on during the handshake is not supported"
+                );
+                MultiplexUpgrade::Unsupported
+            }
+        };
+        MultiplexEarly {
+            peer_id: Some(peer.peer_id),
+            upgrade,
+        }
+    }
+}
+
+impl<C, U, M, N, E> Future for MultiplexEarly<C, U>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: InboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E>,
+    U: OutboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E>,
+    U: InboundConnectionUpgrade<C, Output = N, Error = E>,
+    U: OutboundConnectionUpgrade<C, Output = N, Error = E>,
+{
+    type Output = Result<(PeerId, future::Either<M, N>), UpgradeError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let m = match this.upgrade.project() {
+            MultiplexUpgradeProj::Negotiate(upgrade) => {
+                future::Either::Left(ready!(Future::poll(upgrade, cx))?)
+            }
+            MultiplexUpgradeProj::Inbound(upgrade) => future::Either::Right(
+                ready!(Future::poll(upgrade, cx)).map_err(UpgradeError::Apply)?,
+            ),
+            MultiplexUpgradeProj::Outbound(upgrade) => future::Either::Right(
+                ready!(Future::poll(upgrade, cx)).map_err(UpgradeError::Apply)?,
+            ),
+            MultiplexUpgradeProj::Unsupported => {
+                return Poll::Ready(Err(UpgradeError::Select(NegotiationError::Failed)))
+            }
         };
-        let i = this
-            .peer_id
-            .take()
-            .expect("Multiplex future polled after completion.");
-        Poll::Ready(Ok((i, m)))
+        match this.peer_id.take() {
+            Some(i) => Poll::Ready(Ok((i, m))),
+            None => Poll::Ready(Err(UpgradeError::PolledAfterCompletion)),
+        }
     }
 }
 
-/// An transport with peer authentication, obtained from [`Builder::authenticate`].
+/// The multiplexer upgrade of a [`MultiplexEarly`], negotiated or agreed on during the handshake.
+#[allow(clippy::large_enum_variant)]
+#[pin_project::pin_project(project = MultiplexUpgradeProj)]
+enum MultiplexUpgrade<C, U>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: InboundConnectionUpgrade<Negotiated<C>> + OutboundConnectionUpgrade<Negotiated<C>>,
+    U: InboundConnectionUpgrade<C> + OutboundConnectionUpgrade<C>,
+{
+    Negotiate(#[pin] EitherUpgrade<C, U>),
+    Inbound(#[pin] <U as InboundConnectionUpgrade<C>>::Future),
+    Outbound(#[pin] <U as OutboundConnectionUpgrade<C>>::Future),
+    /// The multiplexer agreed on is not supported by the upgrade.
+    Unsupported,
+}
+
+/// An transport with peer authentication, obtained from [`Builder::authenticate`] or
+/// [`Builder::authenticate2`].
 #[derive(Clone)]
 pub struct Authenticated<T>(Builder<T>);
 
@@ -196,19 +443,20 @@ where
     /// ## Transitions
     ///
     ///   * I/O upgrade: `C -> D`.
-    ///   * Transport output: `(PeerId, C) -> (PeerId, D)`.
+    ///   * Transport output: `(AuthenticatedPeer, C) -> (AuthenticatedPeer, D)`.
     pub fn apply<C, D, U, E>(self, upgrade: U) -> Authenticated<Upgrade<T, U>>
     where
-        T: Transport<Output = (PeerId, C)>,
+        T: Transport<Output = (AuthenticatedPeer, C)>,
         C: AsyncRead + AsyncWrite + Unpin,
         D: AsyncRead + AsyncWrite + Unpin,
         U: InboundConnectionUpgrade<Negotiated<C>, Output = D, Error = E>,
         U: OutboundConnectionUpgrade<Negotiated<C>, Output = D, Error = E> + Clone,
         E: Error + 'static,
     {
+        let version = self.0.version;
         Authenticated(Builder::new(
-            Upgrade::new(self.0.inner, upgrade),
-            self.0.version,
+            Upgrade::new(self.0.inner, upgrade, version),
+            version,
         ))
     }
 
@@ -218,16 +466,22 @@ where
     /// produce a [`StreamMuxer`] `M`. The transport must already be authenticated.
     /// This ends the (regular) transport upgrade process.
     ///
+    /// The multiplexer is always negotiated, even if the security handshake already agreed on
+    /// one. See [`Authenticated::multiplex_early`] to skip that negotiation.
+    ///
     /// ## Transitions
     ///
     ///   * I/O upgrade: `C -> M`.
-    ///   * Transport output: `(PeerId, C) -> (PeerId, M)`.
+    ///   * Transport output: `(AuthenticatedPeer, C) -> (PeerId, M)`.
+    #[allow(clippy::type_complexity)]
     pub fn multiplex<C, M, U, E>(
         self,
         upgrade: U,
-    ) -> Multiplexed<AndThen<T, impl FnOnce((PeerId, C), ConnectedPoint) -> Multiplex<C, U> + Clone>>
+    ) -> Multiplexed<
+        AndThen<T, impl FnOnce((AuthenticatedPeer, C), ConnectedPoint) -> Multiplex<C, U> + Clone>,
+    >
     where
-        T: Transport<Output = (PeerId, C)>,
+        T: Transport<Output = (AuthenticatedPeer, C)>,
         C: AsyncRead + AsyncWrite + Unpin,
         M: StreamMuxer,
         U: InboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E>,
@@ -235,10 +489,10 @@ where
         E: Error + 'static,
     {
         let version = self.0.version;
-        Multiplexed(self.0.inner.and_then(move |(i, c), endpoint| {
+        Multiplexed(self.0.inner.and_then(move |(peer, c), endpoint| {
             let upgrade = crate::apply(c, upgrade, endpoint, version);
             Multiplex {
-                peer_id: Some(i),
+                peer_id: Some(peer.peer_id()),
                 upgrade,
             }
         }))
@@ -254,13 +508,16 @@ where
     /// ## Transitions
     ///
     ///   * I/O upgrade: `C -> M`.
-    ///   * Transport output: `(PeerId, C) -> (PeerId, M)`.
+    ///   * Transport output: `(AuthenticatedPeer, C) -> (PeerId, M)`.
+    #[allow(clippy::type_complexity)]
     pub fn multiplex_ext<C, M, U, E, F>(
         self,
         up: F,
-    ) -> Multiplexed<AndThen<T, impl FnOnce((PeerId, C), ConnectedPoint) -> Multiplex<C, U> + Clone>>
+    ) -> Multiplexed<
+        AndThen<T, impl FnOnce((AuthenticatedPeer, C), ConnectedPoint) -> Multiplex<C, U> + Clone>,
+    >
     where
-        T: Transport<Output = (PeerId, C)>,
+        T: Transport<Output = (AuthenticatedPeer, C)>,
         C: AsyncRead + AsyncWrite + Unpin,
         M: StreamMuxer,
         U: InboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E>,
@@ -269,7 +526,8 @@ where
         F: for<'a> FnOnce(&'a PeerId, &'a ConnectedPoint) -> U + Clone,
     {
         let version = self.0.version;
-        Multiplexed(self.0.inner.and_then(move |(peer_id, c), endpoint| {
+        Multiplexed(self.0.inner.and_then(move |(peer, c), endpoint| {
+            let peer_id = peer.peer_id();
             let upgrade = crate::apply(c, up(&peer_id, &endpoint), endpoint, version);
             Multiplex {
                 peer_id: Some(peer_id),
@@ -277,10 +535,94 @@ where
             }
         }))
     }
+
+    /// Upgrades the transport with a (sub)stream multiplexer, skipping its negotiation if the
+    /// security handshake already agreed on one.
+    ///
+    /// Like [`Authenticated::multiplex`], but if the security handshake agreed on the
+    /// multiplexer, as TLS does with
+    /// [`Config::with_early_muxers`](crate::Config::with_early_muxers), the upgrade is applied
+    /// to the connection `C` itself, producing `N`, so it must support `C` as well as
+    /// `Negotiated<C>`. If the upgrade does not support the multiplexer agreed on, the
+    /// connection fails with [`UpgradeError::Select`].
+    ///
+    /// Both peers must use this method, as a peer using [`Authenticated::multiplex`] still
+    /// expects the multiplexer to be negotiated.
+    ///
+    /// ## Transitions
+    ///
+    ///   * I/O upgrade: `C -> M` or `C -> N`.
+    ///   * Transport output: `(AuthenticatedPeer, C) -> (PeerId, Either<M, N>)`.
+    #[allow(clippy::type_complexity)]
+    pub fn multiplex_early<C, M, N, U, E>(
+        self,
+        upgrade: U,
+    ) -> Multiplexed<
+        AndThen<
+            T,
+            impl FnOnce((AuthenticatedPeer, C), ConnectedPoint) -> MultiplexEarly<C, U> + Clone,
+        >,
+    >
+    where
+        T: Transport<Output = (AuthenticatedPeer, C)>,
+        C: AsyncRead + AsyncWrite + Unpin,
+        M: StreamMuxer,
+        N: StreamMuxer,
+        U: InboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E>,
+        U: OutboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E> + Clone,
+        U: InboundConnectionUpgrade<C, Output = N, Error = E>,
+        U: OutboundConnectionUpgrade<C, Output = N, Error = E>,
+        E: Error + 'static,
+    {
+        let version = self.0.version;
+        Multiplexed(self.0.inner.and_then(move |(peer, c), endpoint| {
+            MultiplexEarly::new(peer, c, upgrade, endpoint, version)
+        }))
+    }
+
+    /// Like [`Authenticated::multiplex_early`] but accepts a function which returns the upgrade.
+    ///
+    /// The supplied function is applied to [`PeerId`] and [`ConnectedPoint`]
+    /// and returns an upgrade which receives the I/O resource `C` and must
+    /// produce a [`StreamMuxer`] `M`, or `N` if the multiplexer was agreed on during the
+    /// security handshake. This ends the (regular) transport upgrade process.
+    ///
+    /// ## Transitions
+    ///
+    ///   * I/O upgrade: `C -> M` or `C -> N`.
+    ///   * Transport output: `(AuthenticatedPeer, C) -> (PeerId, Either<M, N>)`.
+    #[allow(clippy::type_complexity)]
+    pub fn multiplex_early_ext<C, M, N, U, E, F>(
+        self,
+        up: F,
+    ) -> Multiplexed<
+        AndThen<
+            T,
+            impl FnOnce((AuthenticatedPeer, C), ConnectedPoint) -> MultiplexEarly<C, U> + Clone,
+        >,
+    >
+    where
+        T: Transport<Output = (AuthenticatedPeer, C)>,
+        C: AsyncRead + AsyncWrite + Unpin,
+        M: StreamMuxer,
+        N: StreamMuxer,
+        U: InboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E>,
+        U: OutboundConnectionUpgrade<Negotiated<C>, Output = M, Error = E> + Clone,
+        U: InboundConnectionUpgrade<C, Output = N, Error = E>,
+        U: OutboundConnectionUpgrade<C, Output = N, Error = E>,
+        E: Error + 'static,
+        F: for<'a> FnOnce(&'a PeerId, &'a ConnectedPoint) -> U + Clone,
+    {
+        let version = self.0.version;
+        Multiplexed(self.0.inner.and_then(move |(peer, c), endpoint| {
+            let upgrade = up(&peer.peer_id, &endpoint);
+            MultiplexEarly::new(peer, c, upgrade, endpoint, version)
+        }))
+    }
 }
 
 /// A authenticated and multiplexed transport, obtained from
-/// [`Authenticated::multiplex`].
+/// [`Authenticated::multiplex`] or [`Authenticated::multiplex_early`].
 #[derive(Clone)]
 #[pin_project::pin_project]
 pub struct Multiplexed<T>(#[pin] T);
@@ -376,24 +718,30 @@ pub struct Upgrade<T, U> {
     #[pin]
     inner: T,
     upgrade: U,
+    /// The version of multistream-select used on outbound connections.
+    version: upgrade::Version,
 }
 
 impl<T, U> Upgrade<T, U> {
-    pub fn new(inner: T, upgrade: U) -> Self {
-        Upgrade { inner, upgrade }
+    pub fn new(inner: T, upgrade: U, version: upgrade::Version) -> Self {
+        Upgrade {
+            inner,
+            upgrade,
+            version,
+        }
     }
 }
 
 impl<T, C, D, U, E> Transport for Upgrade<T, U>
 where
-    T: Transport<Output = (PeerId, C)>,
+    T: Transport<Output = (AuthenticatedPeer, C)>,
     T::Error: 'static,
     C: AsyncRead + AsyncWrite + Unpin,
     U: InboundConnectionUpgrade<Negotiated<C>, Output = D, Error = E>,
     U: OutboundConnectionUpgrade<Negotiated<C>, Output = D, Error = E> + Clone,
     E: Error + 'static,
 {
-    type Output = (PeerId, D);
+    type Output = (AuthenticatedPeer, D);
     type Error = TransportUpgradeError<T::Error, E>;
     type ListenerUpgrade = ListenerUpgradeFuture<T::ListenerUpgrade, U, C>;
     type Dial = DialUpgradeFuture<T::Dial, U, C>;
@@ -406,6 +754,7 @@ where
         Ok(DialUpgradeFuture {
             future: Box::pin(future),
             upgrade: future::Either::Left(Some(self.upgrade.clone())),
+            version: self.version,
         })
     }
 
@@ -424,6 +773,7 @@ where
         Ok(DialUpgradeFuture {
             future: Box::pin(future),
             upgrade: future::Either::Left(Some(self.upgrade.clone())),
+            version: self.version,
         })
     }
 
@@ -500,17 +850,18 @@ where
     C: AsyncRead + AsyncWrite + Unpin,
 {
     future: Pin<Box<F>>,
-    upgrade: future::Either<Option<U>, (PeerId, OutboundUpgradeApply<C, U>)>,
+    upgrade: future::Either<Option<U>, (AuthenticatedPeer, OutboundUpgradeApply<C, U>)>,
+    version: upgrade::Version,
 }
 
 impl<F, U, C, D> Future for DialUpgradeFuture<F, U, C>
 where
-    F: TryFuture<Ok = (PeerId, C)>,
+    F: TryFuture<Ok = (AuthenticatedPeer, C)>,
     C: AsyncRead + AsyncWrite + Unpin,
     U: OutboundConnectionUpgrade<Negotiated<C>, Output = D>,
     U::Error: Error,
 {
-    type Output = Result<(PeerId, D), TransportUpgradeError<F::Error, U::Error>>;
+    type Output = Result<(AuthenticatedPeer, D), TransportUpgradeError<F::Error, U::Error>>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         // We use a `this` variable because the compiler can't mutably borrow multiple times
@@ -529,16 +880,16 @@ where
                     let u = up
                         .take()
                         .expect("DialUpgradeFuture is constructed with Either::Left(Some).");
-                    future::Either::Right((i, apply_outbound(c, u, upgrade::Version::V1)))
+                    future::Either::Right((i, apply_outbound(c, u, this.version)))
                 }
-                future::Either::Right((i, ref mut up)) => {
+                future::Either::Right((ref i, ref mut up)) => {
                     let d = match ready!(
                         Future::poll(Pin::new(up), cx).map_err(TransportUpgradeError::Upgrade)
                     ) {
                         Ok(d) => d,
                         Err(err) => return Poll::Ready(Err(err)),
                     };
-                    return Poll::Ready(Ok((i, d)));
+                    return Poll::Ready(Ok((i.clone(), d)));
                 }
             }
         }
@@ -559,17 +910,17 @@ where
     U: InboundConnectionUpgrade<Negotiated<C>>,
 {
     future: Pin<Box<F>>,
-    upgrade: future::Either<Option<U>, (PeerId, InboundUpgradeApply<C, U>)>,
+    upgrade: future::Either<Option<U>, (AuthenticatedPeer, InboundUpgradeApply<C, U>)>,
 }
 
 impl<F, U, C, D> Future for ListenerUpgradeFuture<F, U, C>
 where
-    F: TryFuture<Ok = (PeerId, C)>,
+    F: TryFuture<Ok = (AuthenticatedPeer, C)>,
     C: AsyncRead + AsyncWrite + Unpin,
     U: InboundConnectionUpgrade<Negotiated<C>, Output = D>,
     U::Error: Error,
 {
-    type Output = Result<(PeerId, D), TransportUpgradeError<F::Error, U::Error>>;
+    type Output = Result<(AuthenticatedPeer, D), TransportUpgradeError<F::Error, U::Error>>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         // We use a `this` variable because the compiler can't mutably borrow multiple times
@@ -590,14 +941,14 @@ where
                         .expect("ListenerUpgradeFuture is constructed with Either::Left(Some).");
                     future::Either::Right((i, apply_inbound(c, u)))
                 }
-                future::Either::Right((i, ref mut up)) => {
+                future::Either::Right((ref i, ref mut up)) => {
                     let d = match ready!(TryFuture::try_poll(Pin::new(up), cx)
                         .map_err(TransportUpgradeError::Upgrade))
                     {
                         Ok(v) => v,
                         Err(err) => return Poll::Ready(Err(err)),
                     };
-                    return Poll::Ready(Ok((i, d)));
+                    return Poll::Ready(Ok((i.clone(), d)));
                 }
             }
         }
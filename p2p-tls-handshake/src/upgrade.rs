@@ -7,19 +7,36 @@
 //! is considered mandatory, however in practice it is possible for the trait implementation to return
 //! a dummy `Future`.
 
-use crate::certificate::{self, P2pCertificate};
-use crate::error::TlsUpgradeError;
-use crate::{make_client_config, make_server_config};
-use futures::{future::BoxFuture, AsyncRead, AsyncWrite, Future, FutureExt};
-use futures_rustls::TlsStream;
-use libp2p_core::upgrade::UpgradeInfo;
+use crate::certificate::{self, CertificateParams};
+use crate::clock::Clock;
+use crate::connection::{RemoteIdentity, SecuredConnection};
+use crate::error::{RotationError, SecurityAdapterError, TlsUpgradeError};
+use crate::policy::PeerPolicy;
+use crate::resumption::SessionCache;
+use crate::rotation::{RotatingCertResolver, RotationSchedule};
+use crate::store::StoredCertificate;
+use crate::verifier::Libp2pCertificateVerifier;
+use crate::{
+    client_config, client_config_with_rotation, server_config, server_config_builder,
+    server_config_with_rotation, P2P_ALPN,
+};
+use futures::{future::BoxFuture, ready, AsyncRead, AsyncWrite, Future, FutureExt, TryFuture};
+use libp2p_core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade, UpgradeInfo};
 use libp2p_identity::{Keypair, PeerId};
-use rustls::{ClientConfig, ServerConfig};
-use rustls::{CommonState, ServerName};
+use pin_project::pin_project;
+use rustls::client::{ResolvesClientCert, Resumption};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, KeyLog, KeyLogFile, ServerConfig};
+use rustls::{CommonState, ServerName, SignatureScheme};
 use std::net::{IpAddr, Ipv4Addr};
 use std::{
+    io,
     iter::{once, Once},
-    sync::Arc,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{Context, Poll},
+    time::Duration,
 };
 
 /// Possible security upgrade on an inbound connection
@@ -36,6 +53,14 @@ pub trait InboundSecurityUpgrade<T>: UpgradeInfo {
     ///
     /// The `info` is the identifier of the protocol, as produced by `protocol_info`.
     fn secure_inbound(self, socket: T, info: Self::Info) -> Self::Future;
+
+    /// The stream multiplexer agreed on during the handshake that produced `output`, if any.
+    ///
+    /// [`Authenticated::multiplex`](crate::Authenticated::multiplex) then applies the upgrade of
+    /// this multiplexer without negotiating it.
+    fn early_muxer(_output: &Self::Output) -> Option<&str> {
+        None
+    }
 }
 
 /// Possible security upgrade on an outbound connection
@@ -54,21 +79,276 @@ pub trait OutboundSecurityUpgrade<T>: UpgradeInfo {
     /// transports use the optional `peer_id` parameter on outgoing upgrades to validate the
     /// expected `PeerId`.
     fn secure_outbound(self, socket: T, info: Self::Info, peer_id: Option<PeerId>) -> Self::Future;
+
+    /// The stream multiplexer agreed on during the handshake that produced `output`, if any.
+    ///
+    /// See [`InboundSecurityUpgrade::early_muxer`].
+    fn early_muxer(_output: &Self::Output) -> Option<&str> {
+        None
+    }
+}
+
+/// A connection upgrade producing `(PeerId, D)`, such as the noise upgrade of rust-libp2p, used as
+/// security upgrade.
+///
+/// This lets such upgrades be used wherever a security upgrade is expected, e.g. by
+/// [`Builder::authenticate2`](crate::Builder::authenticate2). The upgrade has no way of aborting
+/// its handshake on a mismatch with the [`PeerId`] expected on outbound connections, so the
+/// connection fails with [`SecurityAdapterError::PeerIdMismatch`] once the handshake is over.
+#[derive(Debug, Clone, Copy)]
+pub struct SecurityAdapter<U>(U);
+
+impl<U> SecurityAdapter<U> {
+    /// Wraps the connection upgrade `upgrade`.
+    pub fn new(upgrade: U) -> Self {
+        Self(upgrade)
+    }
+
+    /// Returns the wrapped connection upgrade.
+    pub fn into_inner(self) -> U {
+        self.0
+    }
+}
+
+impl<U: UpgradeInfo> UpgradeInfo for SecurityAdapter<U> {
+    type Info = U::Info;
+    type InfoIter = U::InfoIter;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        self.0.protocol_info()
+    }
+}
+
+impl<T, U, D> InboundSecurityUpgrade<T> for SecurityAdapter<U>
+where
+    U: InboundConnectionUpgrade<T, Output = (PeerId, D)>,
+{
+    type Output = D;
+    type Error = SecurityAdapterError<U::Error>;
+    type Future = SecurityAdapterFuture<U::Future>;
+
+    fn secure_inbound(self, socket: T, info: Self::Info) -> Self::Future {
+        SecurityAdapterFuture {
+            inner: self.0.upgrade_inbound(socket, info),
+            peer_id: None,
+        }
+    }
+}
+
+impl<T, U, D> OutboundSecurityUpgrade<T> for SecurityAdapter<U>
+where
+    U: OutboundConnectionUpgrade<T, Output = (PeerId, D)>,
+{
+    type Output = D;
+    type Error = SecurityAdapterError<U::Error>;
+    type Future = SecurityAdapterFuture<U::Future>;
+
+    fn secure_outbound(self, socket: T, info: Self::Info, peer_id: Option<PeerId>) -> Self::Future {
+        SecurityAdapterFuture {
+            inner: self.0.upgrade_outbound(socket, info),
+            peer_id,
+        }
+    }
+}
+
+/// The handshake of a [`SecurityAdapter`], checking the [`PeerId`] it authenticated against the
+/// expected one, if any.
+#[pin_project]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct SecurityAdapterFuture<F> {
+    #[pin]
+    inner: F,
+    peer_id: Option<PeerId>,
+}
+
+impl<F, D, E> Future for SecurityAdapterFuture<F>
+where
+    F: TryFuture<Ok = (PeerId, D), Error = E>,
+{
+    type Output = Result<(PeerId, D), SecurityAdapterError<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let (expected, output) =
+            ready!(TryFuture::try_poll(this.inner, cx)).map_err(SecurityAdapterError::Upgrade)?;
+
+        if let Some(found) = this.peer_id.filter(|found| *found != expected) {
+            return Poll::Ready(Err(SecurityAdapterError::PeerIdMismatch {
+                expected,
+                found,
+            }));
+        }
+
+        Poll::Ready(Ok((expected, output)))
+    }
 }
 
 #[derive(Clone)]
 pub struct Config {
     server: ServerConfig,
     client: ClientConfig,
+    /// The verifier of both `server` and `client`, caching the peer certificates they verified.
+    verifier: Arc<Libp2pCertificateVerifier>,
+    /// The sessions resumed by both `server` and `client`.
+    sessions: SessionCache,
 }
 
 impl Config {
     pub fn new(identity: &Keypair) -> Result<Self, certificate::GenError> {
+        Self::with_params(identity, &CertificateParams::default())
+    }
+
+    /// Creates a configuration whose certificates are generated according to `params`.
+    pub fn with_params(
+        identity: &Keypair,
+        params: &CertificateParams,
+    ) -> Result<Self, certificate::GenError> {
+        let verifier = Arc::new(Libp2pCertificateVerifier::new());
+        let (server_certificate, server_key) = certificate::generate_with_params(identity, params)?;
+        let (client_certificate, client_key) = certificate::generate_with_params(identity, params)?;
+
+        Ok(Self {
+            server: server_config(verifier.clone(), server_certificate, server_key),
+            client: client_config(verifier.clone(), client_certificate, client_key),
+            verifier,
+            sessions: SessionCache::default(),
+        })
+    }
+
+    /// Creates a configuration whose certificate is generated according to `params` and replaced
+    /// according to `schedule`.
+    ///
+    /// New handshakes present the current certificate, while connections that are already
+    /// established keep using the one they were secured with.
+    pub fn with_rotation(
+        identity: &Keypair,
+        params: CertificateParams,
+        schedule: RotationSchedule,
+    ) -> Result<Self, RotationError> {
+        let resolver = Arc::new(RotatingCertResolver::new(identity, params, schedule)?);
+        let verifier = Arc::new(Libp2pCertificateVerifier::new());
+
         Ok(Self {
-            server: make_server_config(identity)?,
-            client: make_client_config(identity, None)?,
+            server: server_config_with_rotation(verifier.clone(), resolver.clone()),
+            client: client_config_with_rotation(verifier.clone(), resolver),
+            verifier,
+            sessions: SessionCache::default(),
         })
     }
+
+    /// Creates a configuration that presents a previously stored certificate, so that its
+    /// fingerprint stays the same across restarts.
+    pub fn with_certificate(stored: &StoredCertificate) -> Self {
+        let (certificate, private_key) = stored.clone().into_parts();
+        let verifier = Arc::new(Libp2pCertificateVerifier::new());
+
+        Self {
+            server: server_config(verifier.clone(), certificate.clone(), private_key.clone()),
+            client: client_config(verifier.clone(), certificate, private_key),
+            verifier,
+            sessions: SessionCache::default(),
+        }
+    }
+
+    /// Rejects every remote peer that `policy` does not admit, on top of the policies added
+    /// before.
+    ///
+    /// Peers are rejected during the handshake, with an `access_denied` alert. Configurations
+    /// this one was cloned from keep admitting the peers they admitted before.
+    pub fn with_policy(self, policy: impl PeerPolicy + 'static) -> Self {
+        let verifier = self.verifier.with_policy(Arc::new(policy));
+        self.with_verifier(verifier)
+    }
+
+    /// Checks the validity period of peer certificates at the time told by `clock`, instead of
+    /// the system time.
+    ///
+    /// Configurations this one was cloned from keep using their own clock.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        let verifier = self.verifier.with_clock(Arc::new(clock));
+        self.with_verifier(verifier)
+    }
+
+    /// Accepts peer certificates that are expired or not yet valid by up to `clock_skew`, so
+    /// that peers whose clocks are off still interoperate.
+    ///
+    /// Configurations this one was cloned from keep their own tolerance.
+    pub fn with_clock_skew(self, clock_skew: Duration) -> Self {
+        let verifier = self.verifier.with_clock_skew(clock_skew);
+        self.with_verifier(verifier)
+    }
+
+    /// Resumes sessions from `cache` instead of a cache of its own, e.g. to bound it differently.
+    ///
+    /// Every configuration starts with a [`SessionCache::default`], shared by all its clones.
+    pub fn with_session_cache(mut self, cache: SessionCache) -> Self {
+        self.sessions = cache;
+        self
+    }
+
+    /// Offers to agree on a stream multiplexer out of `protocols` during the handshake, in order
+    /// of preference, e.g. `/yamux/1.0.0`.
+    ///
+    /// The protocols are advertised as ALPN values in front of `libp2p`. When both peers support
+    /// one of them, [`Authenticated::multiplex_early`](crate::Authenticated::multiplex_early)
+    /// skips the negotiation of the multiplexer, otherwise it falls back to multistream-select.
+    /// Upgrades applied before the multiplexer are still negotiated, and so is the multiplexer
+    /// with [`Authenticated::multiplex`](crate::Authenticated::multiplex).
+    pub fn with_early_muxers(
+        mut self,
+        protocols: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> Self {
+        let alpn_protocols: Vec<_> = protocols
+            .into_iter()
+            .map(|protocol| protocol.as_ref().as_bytes().to_vec())
+            .chain(once(P2P_ALPN.to_vec()))
+            .collect();
+        self.server.alpn_protocols = alpn_protocols.clone();
+        self.client.alpn_protocols = alpn_protocols;
+        self
+    }
+
+    /// Logs the secrets of every TLS session to `key_log`, so that captured traffic can be
+    /// decrypted, e.g. by Wireshark.
+    ///
+    /// This defeats the confidentiality of every connection secured with this configuration, and
+    /// is only meant for debugging handshakes.
+    pub fn with_key_log(mut self, key_log: impl KeyLog + 'static) -> Self {
+        tracing::warn!(
+            "TLS key logging is enabled, anyone with access to the key log can decrypt the traffic"
+        );
+        let key_log: Arc<dyn KeyLog> = Arc::new(key_log);
+        self.server.key_log = key_log.clone();
+        self.client.key_log = key_log;
+        self
+    }
+
+    /// Replaces the verifier of both `server` and `client`, keeping everything else.
+    fn with_verifier(mut self, verifier: Libp2pCertificateVerifier) -> Self {
+        let verifier = Arc::new(verifier);
+        let mut server = server_config_builder(verifier.clone())
+            .with_cert_resolver(self.server.cert_resolver.clone());
+        server.alpn_protocols = self.server.alpn_protocols.clone();
+        server.key_log = self.server.key_log.clone();
+        self.server = server;
+        self.client
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
+        self.verifier = verifier;
+        self
+    }
+
+    /// Logs the secrets of every TLS session in the NSS key log format to the file named by the
+    /// `SSLKEYLOGFILE` environment variable, if it is set.
+    ///
+    /// See [`with_key_log`](Self::with_key_log).
+    pub fn with_key_log_file(self) -> Self {
+        match std::env::var_os("SSLKEYLOGFILE") {
+            Some(_) => self.with_key_log(KeyLogFile::new()),
+            None => self,
+        }
+    }
 }
 
 impl UpgradeInfo for Config {
@@ -80,65 +360,208 @@ impl UpgradeInfo for Config {
     }
 }
 
+/// Returns the only certificate out of the `certificates` presented by the remote peer.
+///
+/// The verifier rejects any other number of certificates during the handshake, but a
+/// misconfigured TLS configuration may still let one through.
+#[allow(clippy::result_large_err)]
 fn extract_single_certificate(
+    certificates: Option<&[rustls::Certificate]>,
+) -> Result<&rustls::Certificate, TlsUpgradeError> {
+    match certificates.unwrap_or_default() {
+        [certificate] => Ok(certificate),
+        certificates => Err(TlsUpgradeError::CertificateCount(certificates.len())),
+    }
+}
+
+/// Returns the identity of the remote peer, whose certificate has been verified by `verifier`.
+#[allow(clippy::result_large_err)]
+fn remote_identity(
+    verifier: &Libp2pCertificateVerifier,
     state: &CommonState,
-) -> Result<P2pCertificate<'_>, certificate::ParseError> {
-    let Some([cert]) = state.peer_certificates() else {
-        panic!("config enforces exactly one certificate");
-    };
+) -> Result<RemoteIdentity, TlsUpgradeError> {
+    let certificate = extract_single_certificate(state.peer_certificates())?;
+    let verified = verifier.verify_certificate(certificate)?;
+
+    Ok(RemoteIdentity::new(&verified, certificate.clone()))
+}
+
+/// Wraps the rejection of a peer after the handshake like `rustls` wraps it during the handshake.
+fn rejected(error: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Records the certificate that the wrapped resolver picks for a single handshake, so that it
+/// is known even if the certificate rotates.
+pub(crate) struct LocalCertificate<R: ?Sized> {
+    resolver: Arc<R>,
+    resolved: Mutex<Option<Arc<CertifiedKey>>>,
+}
+
+impl<R: ?Sized> LocalCertificate<R> {
+    fn new(resolver: Arc<R>) -> Arc<Self> {
+        Arc::new(Self {
+            resolver,
+            resolved: Mutex::new(None),
+        })
+    }
+
+    fn record(&self, key: Option<Arc<CertifiedKey>>) -> Option<Arc<CertifiedKey>> {
+        *self.resolved.lock().unwrap_or_else(PoisonError::into_inner) = key.clone();
+        key
+    }
 
-    certificate::parse(cert)
+    /// The end-entity certificate picked for the handshake, if any.
+    ///
+    /// The listener always picks one, while the dialer does not when resuming a session.
+    pub(crate) fn certificate(&self) -> Option<rustls::Certificate> {
+        self.resolved
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .and_then(|key| key.cert.first().cloned())
+    }
+}
+
+impl ResolvesServerCert for LocalCertificate<dyn ResolvesServerCert> {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.record(self.resolver.resolve(client_hello))
+    }
+}
+
+impl ResolvesClientCert for LocalCertificate<dyn ResolvesClientCert> {
+    fn resolve(
+        &self,
+        acceptable_issuers: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        self.record(self.resolver.resolve(acceptable_issuers, sigschemes))
+    }
+
+    fn has_certs(&self) -> bool {
+        self.resolver.has_certs()
+    }
 }
 
 impl<C> InboundSecurityUpgrade<C> for Config
 where
     C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
-    type Output = TlsStream<C>;
+    type Output = SecuredConnection<C>;
     type Error = TlsUpgradeError;
     type Future = BoxFuture<'static, Result<(PeerId, Self::Output), Self::Error>>;
 
     fn secure_inbound(self, socket: C, _: Self::Info) -> Self::Future {
         async move {
-            let stream = futures_rustls::TlsAcceptor::from(Arc::new(self.server))
+            let mut server = self.server;
+            let local = LocalCertificate::new(server.cert_resolver);
+            server.cert_resolver = local.clone();
+            server.ticketer = self.sessions.inbound(local.clone());
+
+            let stream = futures_rustls::TlsAcceptor::from(Arc::new(server))
                 .accept(socket)
                 .await
                 .map_err(TlsUpgradeError::ServerUpgrade)?;
 
-            let expected = extract_single_certificate(stream.get_ref().1)?.peer_id();
+            let remote = remote_identity(&self.verifier, stream.get_ref().1)?;
+            let resumed = stream.get_ref().1.received_resumption_data().is_some();
+            if resumed {
+                self.verifier
+                    .admit(remote.public_key())
+                    .map_err(|e| TlsUpgradeError::ServerUpgrade(rejected(e)))?;
+            }
+            let local_certificate = local
+                .certificate()
+                .ok_or(TlsUpgradeError::MissingLocalCertificate)?;
 
-            Ok((expected, stream.into()))
+            Ok((
+                remote.peer_id(),
+                SecuredConnection::new(stream.into(), local_certificate, remote, resumed)?,
+            ))
         }
         .boxed()
     }
+
+    fn early_muxer(output: &Self::Output) -> Option<&str> {
+        output.early_muxer()
+    }
 }
 
 impl<C> OutboundSecurityUpgrade<C> for Config
 where
     C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
 {
-    type Output = TlsStream<C>;
+    type Output = SecuredConnection<C>;
     type Error = TlsUpgradeError;
     type Future = BoxFuture<'static, Result<(PeerId, Self::Output), Self::Error>>;
 
     fn secure_outbound(self, socket: C, _: Self::Info, peer_id: Option<PeerId>) -> Self::Future {
         async move {
             let name = ServerName::IpAddress(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            let mut client = self.client;
+            let local = LocalCertificate::new(client.client_auth_cert_resolver);
+            client.client_auth_cert_resolver = local.clone();
+            let sessions = self.sessions.outbound(peer_id);
+            client.resumption = Resumption::store(sessions.clone());
 
-            let stream = futures_rustls::TlsConnector::from(Arc::new(self.client))
+            let stream = futures_rustls::TlsConnector::from(Arc::new(client))
                 .connect(name, socket)
                 .await
                 .map_err(TlsUpgradeError::ClientUpgrade)?;
 
-            let expected = extract_single_certificate(stream.get_ref().1)?.peer_id();
+            let remote = remote_identity(&self.verifier, stream.get_ref().1)?;
+            let expected = remote.peer_id();
 
-            match peer_id {
-                Some(found) if found != expected => {
-                    Err(TlsUpgradeError::PeerIdMismatch { expected, found })
-                }
-                _ => Ok((expected, stream.into())),
+            if let Some(found) = peer_id.filter(|found| *found != expected) {
+                return Err(TlsUpgradeError::PeerIdMismatch { expected, found });
             }
+
+            // The dialer only presents a certificate in full handshakes.
+            let (local_certificate, resumed) = match local.certificate() {
+                Some(certificate) => (certificate, false),
+                None => {
+                    self.verifier
+                        .admit(remote.public_key())
+                        .map_err(|e| TlsUpgradeError::ClientUpgrade(rejected(e)))?;
+                    let certificate = sessions
+                        .offered_certificate()
+                        .ok_or(TlsUpgradeError::MissingLocalCertificate)?;
+                    (certificate, true)
+                }
+            };
+            sessions.establish(local_certificate.clone());
+
+            Ok((
+                expected,
+                SecuredConnection::new(stream.into(), local_certificate, remote, resumed)?,
+            ))
         }
         .boxed()
     }
+
+    fn early_muxer(output: &Self::Output) -> Option<&str> {
+        output.early_muxer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_single_certificate_rejects_other_counts() {
+        let certificate = rustls::Certificate(vec![1, 2, 3]);
+        let certificates = [certificate.clone(), certificate.clone()];
+
+        assert_eq!(
+            extract_single_certificate(Some(&certificates[..1])).unwrap(),
+            &certificate
+        );
+        for (certificates, count) in [(None, 0), (Some(&[][..]), 0), (Some(&certificates[..]), 2)] {
+            assert!(matches!(
+                extract_single_certificate(certificates),
+                Err(TlsUpgradeError::CertificateCount(found)) if found == count
+            ));
+        }
+    }
 }
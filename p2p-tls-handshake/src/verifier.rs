@@ -3,8 +3,11 @@
 //! This module handles a verification of a client/server certificate chain
 //! and signatures allegedly by the given certificates.
 
-use crate::certificate::{self, VerificationError};
-use libp2p_identity::PeerId;
+use crate::certificate::{self, VerificationError, VerifiedCertificate};
+use crate::clock::Clock;
+use crate::error::TlsUpgradeError;
+use crate::policy::PeerPolicy;
+use libp2p_identity::{PeerId, PublicKey};
 use rustls::{
     cipher_suite::{
         TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384, TLS13_CHACHA20_POLY1305_SHA256,
@@ -14,7 +17,9 @@ use rustls::{
     Certificate, CertificateError, DigitallySignedStruct, DistinguishedName, SignatureScheme,
     SupportedCipherSuite, SupportedProtocolVersion,
 };
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
 
 /// The protocol versions supported by this verifier.
 ///
@@ -33,12 +38,34 @@ pub(crate) static CIPHERSUITES: &[SupportedCipherSuite] = &[
     TLS13_AES_128_GCM_SHA256,
 ];
 
+/// How many verified certificates a verifier remembers.
+const CACHE_CAPACITY: usize = 64;
+
 /// Implementation of the `rustls` certificate verification traits for libp2p.
 ///
 /// Only TLS 1.3 is supported. TLS 1.2 should be disabled in the configuration of `rustls`.
 pub(crate) struct Libp2pCertificateVerifier {
     /// The peer ID we intend to connect to
     remote_peer_id: Option<PeerId>,
+    /// The most recently verified certificates, keyed by their DER encoding.
+    ///
+    /// A handshake needs the peer certificate when verifying the certificate chain, when
+    /// verifying the handshake signature and when extracting the peer ID of the secured
+    /// connection. The cache lets all of them share a single parse and verification.
+    verified: Mutex<VecDeque<(Certificate, Arc<VerifiedCertificate>)>>,
+    /// The policies every remote peer must be admitted by.
+    policies: Vec<Arc<dyn PeerPolicy>>,
+    /// How the validity period of peer certificates is checked.
+    validity: ValidityCheck,
+}
+
+/// The time at which the validity period of peer certificates is checked.
+#[derive(Clone, Default)]
+struct ValidityCheck {
+    /// Replaces the time reported by `rustls`.
+    clock: Option<Arc<dyn Clock>>,
+    /// How far the clocks of both peers may be apart.
+    clock_skew: Duration,
 }
 
 /// libp2p requires the following of X.509 server certificate chains:
@@ -49,12 +76,96 @@ pub(crate) struct Libp2pCertificateVerifier {
 ///   signature of its public key.
 impl Libp2pCertificateVerifier {
     pub(crate) fn new() -> Self {
+        Self::with_remote_peer_id(None)
+    }
+    pub(crate) fn with_remote_peer_id(remote_peer_id: Option<PeerId>) -> Self {
         Self {
-            remote_peer_id: None,
+            remote_peer_id,
+            verified: Mutex::new(VecDeque::with_capacity(CACHE_CAPACITY)),
+            policies: Vec::new(),
+            validity: ValidityCheck::default(),
         }
     }
-    pub(crate) fn with_remote_peer_id(remote_peer_id: Option<PeerId>) -> Self {
-        Self { remote_peer_id }
+
+    /// Returns a verifier that checks the validity period of peer certificates at the time told
+    /// by `clock`.
+    pub(crate) fn with_clock(&self, clock: Arc<dyn Clock>) -> Self {
+        let mut verifier = self.derive();
+        verifier.validity.clock = Some(clock);
+        verifier
+    }
+
+    /// Returns a verifier that accepts peer certificates that are expired or not yet valid by up
+    /// to `clock_skew`.
+    pub(crate) fn with_clock_skew(&self, clock_skew: Duration) -> Self {
+        let mut verifier = self.derive();
+        verifier.validity.clock_skew = clock_skew;
+        verifier
+    }
+
+    /// Returns a verifier that also requires every remote peer to be admitted by `policy`, on
+    /// top of the policies of this one.
+    pub(crate) fn with_policy(&self, policy: Arc<dyn PeerPolicy>) -> Self {
+        let mut verifier = self.derive();
+        verifier.policies.push(policy);
+        verifier
+    }
+
+    /// Returns a verifier with the same settings as this one and an empty cache.
+    ///
+    /// The settings of a verifier never change, so that configurations sharing it are not
+    /// affected by changes made to a copy of one of them.
+    fn derive(&self) -> Self {
+        Self {
+            remote_peer_id: self.remote_peer_id,
+            verified: Mutex::new(VecDeque::with_capacity(CACHE_CAPACITY)),
+            policies: self.policies.clone(),
+            validity: self.validity.clone(),
+        }
+    }
+
+    /// Parses and verifies `certificate`, or returns the result of a previous verification of the
+    /// same certificate that is still within its validity period.
+    pub(crate) fn verify_certificate(
+        &self,
+        certificate: &Certificate,
+    ) -> Result<Arc<VerifiedCertificate>, certificate::ParseError> {
+        self.verify_certificate_at(certificate, SystemTime::now())
+    }
+
+    /// Like [`verify_certificate`](Self::verify_certificate), but `now` is the time reported by
+    /// `rustls`, which is used unless a clock has been set.
+    fn verify_certificate_at(
+        &self,
+        certificate: &Certificate,
+        now: SystemTime,
+    ) -> Result<Arc<VerifiedCertificate>, certificate::ParseError> {
+        let now = self
+            .validity
+            .clock
+            .as_ref()
+            .map_or(now, |clock| clock.now());
+        let clock_skew = self.validity.clock_skew;
+
+        let mut verified = self.verified.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(index) = verified.iter().position(|(der, _)| der == certificate) {
+            let (_, cached) = &verified[index];
+            if cached.is_valid_at(now, clock_skew) {
+                return Ok(cached.clone());
+            }
+            verified.remove(index);
+        }
+        drop(verified);
+
+        let cached = Arc::new(certificate::parse_verified(certificate, now, clock_skew)?);
+
+        let mut verified = self.verified.lock().unwrap_or_else(PoisonError::into_inner);
+        if verified.len() == CACHE_CAPACITY {
+            verified.pop_front();
+        }
+        verified.push_back((certificate.clone(), cached.clone()));
+
+        Ok(cached)
     }
 
     /// Return the list of SignatureSchemes that this verifier will handle,
@@ -88,9 +199,10 @@ impl ServerCertVerifier for Libp2pCertificateVerifier {
         _server_name: &rustls::ServerName,
         _scts: &mut dyn Iterator<Item = &[u8]>,
         _ocsp_response: &[u8],
-        _now: std::time::SystemTime,
+        now: SystemTime,
     ) -> Result<ServerCertVerified, rustls::Error> {
-        let peer_id = verify_presented_certs(end_entity, intermediates)?;
+        let cert = self.verify_presented_certs(end_entity, intermediates, now)?;
+        let peer_id = cert.peer_id();
 
         if let Some(remote_peer_id) = self.remote_peer_id {
             // The public host key allows the peer to calculate the peer ID of the peer
@@ -103,6 +215,7 @@ impl ServerCertVerifier for Libp2pCertificateVerifier {
                 ));
             }
         }
+        self.admit(cert.public_key())?;
 
         Ok(ServerCertVerified::assertion())
     }
@@ -113,7 +226,7 @@ impl ServerCertVerifier for Libp2pCertificateVerifier {
         _cert: &Certificate,
         _dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        unreachable!("`PROTOCOL_VERSIONS` only allows TLS 1.3")
+        Err(tls12_signature_error())
     }
 
     fn verify_tls13_signature(
@@ -122,7 +235,7 @@ impl ServerCertVerifier for Libp2pCertificateVerifier {
         cert: &Certificate,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        verify_tls13_signature(cert, dss.scheme, message, dss.signature())
+        self.verify_handshake_signature(cert, dss.scheme, message, dss.signature())
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
@@ -150,9 +263,10 @@ impl ClientCertVerifier for Libp2pCertificateVerifier {
         &self,
         end_entity: &Certificate,
         intermediates: &[Certificate],
-        _now: std::time::SystemTime,
+        now: SystemTime,
     ) -> Result<ClientCertVerified, rustls::Error> {
-        verify_presented_certs(end_entity, intermediates)?;
+        let cert = self.verify_presented_certs(end_entity, intermediates, now)?;
+        self.admit(cert.public_key())?;
 
         Ok(ClientCertVerified::assertion())
     }
@@ -163,7 +277,7 @@ impl ClientCertVerifier for Libp2pCertificateVerifier {
         _cert: &Certificate,
         _dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        unreachable!("`PROTOCOL_VERSIONS` only allows TLS 1.3")
+        Err(tls12_signature_error())
     }
 
     fn verify_tls13_signature(
@@ -172,7 +286,7 @@ impl ClientCertVerifier for Libp2pCertificateVerifier {
         cert: &Certificate,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        verify_tls13_signature(cert, dss.scheme, message, dss.signature())
+        self.verify_handshake_signature(cert, dss.scheme, message, dss.signature())
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
@@ -180,36 +294,65 @@ impl ClientCertVerifier for Libp2pCertificateVerifier {
     }
 }
 
-/// When receiving the certificate chain, an endpoint
-/// MUST check these conditions and abort the connection attempt if
-/// (a) the presented certificate is not yet valid, OR
-/// (b) if it is expired.
-/// Endpoints MUST abort the connection attempt if more than one certificate is received,
-/// or if the certificate’s self-signature is not valid.
-fn verify_presented_certs(
-    end_entity: &Certificate,
-    intermediates: &[Certificate],
-) -> Result<PeerId, rustls::Error> {
-    if !intermediates.is_empty() {
-        return Err(rustls::Error::General(
-            "p2p-tls requires exactly one certificate".into(),
-        ));
+/// Rejects a TLS 1.2 handshake signature.
+///
+/// `PROTOCOL_VERSIONS` only allows TLS 1.3, so this only happens with a TLS configuration that
+/// enables TLS 1.2 on top of this verifier.
+fn tls12_signature_error() -> rustls::Error {
+    rustls::Error::InvalidCertificate(CertificateError::Other(Arc::new(
+        TlsUpgradeError::UnsupportedTls12Signature,
+    )))
+}
+
+impl Libp2pCertificateVerifier {
+    /// When receiving the certificate chain, an endpoint
+    /// MUST check these conditions and abort the connection attempt if
+    /// (a) the presented certificate is not yet valid, OR
+    /// (b) if it is expired.
+    /// Endpoints MUST abort the connection attempt if more than one certificate is received,
+    /// or if the certificate’s self-signature is not valid.
+    fn verify_presented_certs(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        now: SystemTime,
+    ) -> Result<Arc<VerifiedCertificate>, rustls::Error> {
+        if !intermediates.is_empty() {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+                Arc::new(TlsUpgradeError::CertificateCount(intermediates.len() + 1)),
+            )));
+        }
+
+        Ok(self.verify_certificate_at(end_entity, now)?)
     }
 
-    let cert = certificate::parse(end_entity)?;
+    /// Rejects the peer with the host key `public_key` unless every policy admits it.
+    ///
+    /// This happens while verifying the peer certificate, except for resumed sessions, whose
+    /// peer certificate is not verified again.
+    pub(crate) fn admit(&self, public_key: &PublicKey) -> Result<(), rustls::Error> {
+        if !self.policies.iter().all(|policy| policy.admit(public_key)) {
+            tracing::debug!(peer_id=%public_key.to_peer_id(), "Peer rejected by policy");
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
 
-    Ok(cert.peer_id())
-}
+        Ok(())
+    }
 
-fn verify_tls13_signature(
-    cert: &Certificate,
-    signature_scheme: SignatureScheme,
-    message: &[u8],
-    signature: &[u8],
-) -> Result<HandshakeSignatureValid, rustls::Error> {
-    certificate::parse(cert)?.verify_signature(signature_scheme, message, signature)?;
+    fn verify_handshake_signature(
+        &self,
+        cert: &Certificate,
+        signature_scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_certificate(cert)?
+            .verify_signature(signature_scheme, message, signature)?;
 
-    Ok(HandshakeSignatureValid::assertion())
+        Ok(HandshakeSignatureValid::assertion())
+    }
 }
 
 impl From<certificate::ParseError> for rustls::Error {
@@ -232,3 +375,191 @@ impl From<VerificationError> for rustls::Error {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p_identity::Keypair;
+    use rustls::{ClientConfig, ClientConnection, ServerConfig, ServerConnection, ServerName};
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn generate() -> Certificate {
+        certificate::generate(&Keypair::generate_ed25519())
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn verify_certificate_reuses_verification() {
+        let verifier = Libp2pCertificateVerifier::new();
+        let certificate = generate();
+
+        let first = verifier.verify_certificate(&certificate).unwrap();
+        let second = verifier.verify_certificate(&certificate).unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[test]
+    fn verify_certificate_does_not_cache_failures() {
+        let verifier = Libp2pCertificateVerifier::new();
+        let mut certificate = generate();
+        let last = certificate.0.len() - 1;
+        certificate.0[last] ^= 0xff;
+
+        assert!(verifier.verify_certificate(&certificate).is_err());
+        assert!(verifier.verify_certificate(&certificate).is_err());
+        assert!(verifier.verified.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn verify_certificate_evicts_oldest() {
+        let verifier = Libp2pCertificateVerifier::new();
+        let oldest = generate();
+        let first = verifier.verify_certificate(&oldest).unwrap();
+
+        for _ in 0..CACHE_CAPACITY {
+            verifier.verify_certificate(&generate()).unwrap();
+        }
+        let second = verifier.verify_certificate(&oldest).unwrap();
+
+        assert_eq!(verifier.verified.lock().unwrap().len(), CACHE_CAPACITY);
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!(first.peer_id(), second.peer_id());
+    }
+
+    /// Accepts any server certificate and handshake signature.
+    struct AcceptAnyServer;
+
+    impl ServerCertVerifier for AcceptAnyServer {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            _message: &[u8],
+            _cert: &Certificate,
+            _dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            Ok(HandshakeSignatureValid::assertion())
+        }
+    }
+
+    fn tls12_client_config() -> rustls::ConfigBuilder<ClientConfig, rustls::WantsVerifier> {
+        ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS12])
+            .unwrap()
+    }
+
+    fn tls12_server_config() -> rustls::ConfigBuilder<ServerConfig, rustls::WantsVerifier> {
+        ServerConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS12])
+            .unwrap()
+    }
+
+    /// Performs the handshake of `client` and `server` in memory, failing with the first error of
+    /// either.
+    fn handshake(client: ClientConfig, server: ServerConfig) -> Result<(), rustls::Error> {
+        let name = ServerName::IpAddress(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let mut client = ClientConnection::new(Arc::new(client), name)?;
+        let mut server = ServerConnection::new(Arc::new(server))?;
+        let mut buf = Vec::new();
+
+        while client.is_handshaking() || server.is_handshaking() {
+            buf.clear();
+            client.write_tls(&mut buf).unwrap();
+            let mut data = &buf[..];
+            while !data.is_empty() {
+                server.read_tls(&mut data).unwrap();
+                server.process_new_packets()?;
+            }
+
+            buf.clear();
+            server.write_tls(&mut buf).unwrap();
+            let mut data = &buf[..];
+            while !data.is_empty() {
+                client.read_tls(&mut data).unwrap();
+                client.process_new_packets()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn assert_tls12_signature_rejected(result: Result<(), rustls::Error>) {
+        let Err(rustls::Error::InvalidCertificate(CertificateError::Other(error))) = result else {
+            panic!("TLS 1.2 signature was not rejected: {result:?}");
+        };
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(TlsUpgradeError::UnsupportedTls12Signature)
+        ));
+    }
+
+    #[test]
+    fn tls12_server_signature_is_rejected() {
+        let (certificate, private_key) =
+            certificate::generate(&Keypair::generate_ed25519()).unwrap();
+        let client = tls12_client_config()
+            .with_custom_certificate_verifier(Arc::new(Libp2pCertificateVerifier::new()))
+            .with_no_client_auth();
+        let server = tls12_server_config()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate], private_key)
+            .unwrap();
+
+        assert_tls12_signature_rejected(handshake(client, server));
+    }
+
+    #[test]
+    fn tls12_client_signature_is_rejected() {
+        let (client_certificate, client_key) =
+            certificate::generate(&Keypair::generate_ed25519()).unwrap();
+        let (server_certificate, server_key) =
+            certificate::generate(&Keypair::generate_ed25519()).unwrap();
+        let client = tls12_client_config()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyServer))
+            .with_client_auth_cert(vec![client_certificate], client_key)
+            .unwrap();
+        let server = tls12_server_config()
+            .with_client_cert_verifier(Arc::new(Libp2pCertificateVerifier::new()))
+            .with_single_cert(vec![server_certificate], server_key)
+            .unwrap();
+
+        assert_tls12_signature_rejected(handshake(client, server));
+    }
+
+    #[test]
+    fn verify_certificate_uses_clock() {
+        let keypair = Keypair::generate_ed25519();
+        let not_before = SystemTime::now();
+        let not_after = not_before + Duration::from_secs(3600);
+        let params = certificate::CertificateParams::new().with_validity(not_before, not_after);
+        let (certificate, _) = certificate::generate_with_params(&keypair, &params).unwrap();
+        let now = Arc::new(Mutex::new(not_before));
+        let clock = now.clone();
+        let verifier =
+            Libp2pCertificateVerifier::new().with_clock(Arc::new(move || *clock.lock().unwrap()));
+        verifier.verify_certificate(&certificate).unwrap();
+
+        // The cached verification is checked against the clock as well.
+        *now.lock().unwrap() = not_after + Duration::from_secs(60);
+        assert!(verifier.verify_certificate(&certificate).is_err());
+
+        let verifier = verifier.with_clock_skew(Duration::from_secs(120));
+        assert!(verifier.verify_certificate(&certificate).is_ok());
+    }
+}
@@ -0,0 +1,415 @@
+//! Interoperability tests against the upstream `libp2p-tls` and `libp2p-noise` implementations.
+//!
+//! Every combination of host key types is run in both directions: our dialer against an upstream
+//! listener and an upstream dialer against our listener.
+
+use crate::utils::identity::keypairs;
+use crate::utils::memory::{connect, dialer_endpoint, listener_endpoint, memory_connection};
+use futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p_core::{
+    multiaddr::Protocol,
+    muxing::StreamMuxerBox,
+    transport::MemoryTransport,
+    upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade},
+    Negotiated, UpgradeInfo,
+};
+use libp2p_identity::{Keypair, PeerId};
+use p2p_tls_handshake::{
+    secure, Boxed, Builder, Config, NoiseConfig, NoiseUpgradeError, SecuredConnection,
+    SecurityAdapter, SecurityAdapterError, TlsStream, UpgradeError, Version,
+};
+use std::error::Error;
+
+pub mod utils;
+
+#[tokio::test]
+async fn dialer_interoperates_with_libp2p_tls_listener() {
+    for dialer_keypair in keypairs() {
+        for listener_keypair in keypairs() {
+            let listener_peer_id = listener_keypair.public().to_peer_id();
+            let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+            let (dialer, listener) = future::join(
+                secure(
+                    dialer_conn,
+                    Config::new(&dialer_keypair).unwrap(),
+                    dialer_endpoint(addr.with(Protocol::P2p(listener_peer_id))),
+                    Version::V1,
+                ),
+                libp2p_tls_inbound(listener_conn, &listener_keypair),
+            )
+            .await;
+            let (dialer_peer_id, mut dialer_stream) = dialer.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} dialer failed against {:?} libp2p-tls listener: {e}",
+                    dialer_keypair.key_type(),
+                    listener_keypair.key_type()
+                )
+            });
+            let (listener_peer_id, mut listener_stream) = listener.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} libp2p-tls listener failed against {:?} dialer: {e}",
+                    listener_keypair.key_type(),
+                    dialer_keypair.key_type()
+                )
+            });
+
+            assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+            assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+            assert_metadata(&dialer_stream, &listener_stream, &listener_keypair);
+            assert_same_session(dialer_stream.get_mut(), &mut listener_stream).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn listener_interoperates_with_libp2p_tls_dialer() {
+    for dialer_keypair in keypairs() {
+        for listener_keypair in keypairs() {
+            let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+            let (dialer, listener) = future::join(
+                libp2p_tls_outbound(dialer_conn, &dialer_keypair),
+                secure(
+                    listener_conn,
+                    Config::new(&listener_keypair).unwrap(),
+                    listener_endpoint(addr),
+                    Version::V1,
+                ),
+            )
+            .await;
+            let (dialer_peer_id, mut dialer_stream) = dialer.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} libp2p-tls dialer failed against {:?} listener: {e}",
+                    dialer_keypair.key_type(),
+                    listener_keypair.key_type()
+                )
+            });
+            let (listener_peer_id, mut listener_stream) = listener.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} listener failed against {:?} libp2p-tls dialer: {e}",
+                    listener_keypair.key_type(),
+                    dialer_keypair.key_type()
+                )
+            });
+
+            assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+            assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+            assert_metadata(&listener_stream, &dialer_stream, &dialer_keypair);
+            assert_same_session(&mut dialer_stream, listener_stream.get_mut()).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn noise_dialer_interoperates_with_libp2p_noise_listener() {
+    for dialer_keypair in keypairs() {
+        for listener_keypair in keypairs() {
+            let listener_peer_id = listener_keypair.public().to_peer_id();
+            let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+            let (dialer, listener) = future::join(
+                secure(
+                    dialer_conn,
+                    NoiseConfig::new(&dialer_keypair).unwrap(),
+                    dialer_endpoint(addr.with(Protocol::P2p(listener_peer_id))),
+                    Version::V1,
+                ),
+                libp2p_noise_inbound(listener_conn, &listener_keypair),
+            )
+            .await;
+            let (dialer_peer_id, dialer_stream) = dialer.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} Noise dialer failed against {:?} libp2p-noise listener: {e}",
+                    dialer_keypair.key_type(),
+                    listener_keypair.key_type()
+                )
+            });
+            let (listener_peer_id, listener_stream) = listener.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} libp2p-noise listener failed against {:?} Noise dialer: {e}",
+                    listener_keypair.key_type(),
+                    dialer_keypair.key_type()
+                )
+            });
+
+            assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+            assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+            assert_exchange(dialer_stream, listener_stream).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn noise_listener_interoperates_with_libp2p_noise_dialer() {
+    for dialer_keypair in keypairs() {
+        for listener_keypair in keypairs() {
+            let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+            let (dialer, listener) = future::join(
+                libp2p_noise_outbound(dialer_conn, &dialer_keypair),
+                secure(
+                    listener_conn,
+                    NoiseConfig::new(&listener_keypair).unwrap(),
+                    listener_endpoint(addr),
+                    Version::V1,
+                ),
+            )
+            .await;
+            let (dialer_peer_id, dialer_stream) = dialer.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} libp2p-noise dialer failed against {:?} Noise listener: {e}",
+                    dialer_keypair.key_type(),
+                    listener_keypair.key_type()
+                )
+            });
+            let (listener_peer_id, listener_stream) = listener.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} Noise listener failed against {:?} libp2p-noise dialer: {e}",
+                    listener_keypair.key_type(),
+                    dialer_keypair.key_type()
+                )
+            });
+
+            assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+            assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+            assert_exchange(dialer_stream, listener_stream).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn noise_dialer_aborts_on_peer_id_mismatch() {
+    let listener_keypair = Keypair::generate_ed25519();
+    let unexpected_peer_id = PeerId::random();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            NoiseConfig::new(&Keypair::generate_ed25519()).unwrap(),
+            dialer_endpoint(addr.with(Protocol::P2p(unexpected_peer_id))),
+            Version::V1,
+        ),
+        libp2p_noise_inbound(listener_conn, &listener_keypair),
+    )
+    .await;
+
+    match dialer {
+        Err(UpgradeError::Apply(NoiseUpgradeError::PeerIdMismatch { expected, found })) => {
+            assert_eq!(expected, listener_keypair.public().to_peer_id());
+            assert_eq!(found, unexpected_peer_id);
+        }
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("dialer accepted a listener with an unexpected peer ID"),
+    }
+    // The dialer hangs up instead of authenticating itself.
+    assert!(listener.is_err());
+}
+
+#[tokio::test]
+async fn security_adapter_rejects_peer_id_mismatch_with_libp2p_noise() {
+    let listener_keypair = Keypair::generate_ed25519();
+    let unexpected_peer_id = PeerId::random();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, _) = future::join(
+        secure(
+            dialer_conn,
+            SecurityAdapter::new(libp2p_noise::Config::new(&Keypair::generate_ed25519()).unwrap()),
+            dialer_endpoint(addr.with(Protocol::P2p(unexpected_peer_id))),
+            Version::V1,
+        ),
+        libp2p_noise_inbound(listener_conn, &listener_keypair),
+    )
+    .await;
+
+    match dialer {
+        Err(UpgradeError::Apply(SecurityAdapterError::PeerIdMismatch { expected, found })) => {
+            assert_eq!(expected, listener_keypair.public().to_peer_id());
+            assert_eq!(found, unexpected_peer_id);
+        }
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("dialer accepted a listener with an unexpected peer ID"),
+    }
+}
+
+#[tokio::test]
+async fn authenticate_upgrades_with_libp2p_tls() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+
+    let (dialer, listener) = connect(
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate(libp2p_tls::Config::new(&dialer_keypair).unwrap())
+            .multiplex(libp2p_yamux::Config::default())
+            .boxed(),
+        ours(&listener_keypair),
+        None,
+    )
+    .await;
+
+    let (dialer_peer_id, _) = dialer.expect("libp2p-tls dialer to authenticate the listener");
+    let (listener_peer_id, _) = listener.expect("listener to authenticate the libp2p-tls dialer");
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+}
+
+#[tokio::test]
+async fn security_adapter_upgrades_with_libp2p_tls() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+
+    let (dialer, listener) = connect(
+        ours(&dialer_keypair),
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(SecurityAdapter::new(
+                libp2p_tls::Config::new(&listener_keypair).unwrap(),
+            ))
+            .multiplex(libp2p_yamux::Config::default())
+            .boxed(),
+        Some(listener_peer_id),
+    )
+    .await;
+
+    let (dialer_peer_id, _) = dialer.expect("dialer to authenticate the libp2p-tls listener");
+    let (listener_peer_id, _) = listener.expect("libp2p-tls listener to authenticate the dialer");
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+}
+
+/// A transport authenticated by our security upgrade and multiplexed with yamux.
+fn ours(keypair: &Keypair) -> Boxed<(PeerId, StreamMuxerBox)> {
+    Builder::new(MemoryTransport::default(), Version::V1)
+        .authenticate2(Config::new(keypair).unwrap())
+        .multiplex(libp2p_yamux::Config::default())
+        .boxed()
+}
+
+type Libp2pTlsOutput<C> = Result<(PeerId, TlsStream<Negotiated<C>>), Box<dyn Error>>;
+
+/// Negotiates `/tls/1.0.0` and secures an inbound connection with `libp2p_tls`.
+async fn libp2p_tls_inbound<C>(conn: C, keypair: &Keypair) -> Libp2pTlsOutput<C>
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let config = libp2p_tls::Config::new(keypair)?;
+    let (info, stream) =
+        multistream_select::listener_select_proto(conn, config.protocol_info()).await?;
+
+    Ok(config.upgrade_inbound(stream, info).await?)
+}
+
+/// Negotiates `/tls/1.0.0` and secures an outbound connection with `libp2p_tls`.
+async fn libp2p_tls_outbound<C>(conn: C, keypair: &Keypair) -> Libp2pTlsOutput<C>
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let config = libp2p_tls::Config::new(keypair)?;
+    let (info, stream) =
+        multistream_select::dialer_select_proto(conn, config.protocol_info(), Version::V1).await?;
+
+    Ok(config.upgrade_outbound(stream, info).await?)
+}
+
+type Libp2pNoiseOutput<C> = Result<(PeerId, libp2p_noise::Output<Negotiated<C>>), Box<dyn Error>>;
+
+/// Negotiates `/noise` and secures an inbound connection with `libp2p_noise`.
+async fn libp2p_noise_inbound<C>(conn: C, keypair: &Keypair) -> Libp2pNoiseOutput<C>
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let config = libp2p_noise::Config::new(keypair)?;
+    let (info, stream) =
+        multistream_select::listener_select_proto(conn, config.protocol_info()).await?;
+
+    Ok(config.upgrade_inbound(stream, info).await?)
+}
+
+/// Negotiates `/noise` and secures an outbound connection with `libp2p_noise`.
+async fn libp2p_noise_outbound<C>(conn: C, keypair: &Keypair) -> Libp2pNoiseOutput<C>
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let config = libp2p_noise::Config::new(keypair)?;
+    let (info, stream) =
+        multistream_select::dialer_select_proto(conn, config.protocol_info(), Version::V1).await?;
+
+    Ok(config.upgrade_outbound(stream, info).await?)
+}
+
+/// Asserts that both ends exchange data in both directions, including data spanning several
+/// Noise messages.
+async fn assert_exchange<D, L>(mut dialer: D, mut listener: L)
+where
+    D: AsyncRead + AsyncWrite + Unpin,
+    L: AsyncRead + AsyncWrite + Unpin,
+{
+    let data: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+    let send = async {
+        dialer.write_all(&data).await.unwrap();
+        dialer.flush().await.unwrap();
+    };
+    let mut received = vec![0; data.len()];
+    future::join(send, listener.read_exact(&mut received))
+        .await
+        .1
+        .unwrap();
+    assert!(received == data);
+
+    listener.write_all(b"pong").await.unwrap();
+    listener.flush().await.unwrap();
+    let mut buf = [0u8; 4];
+    dialer.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+}
+
+/// Asserts that the metadata of our end matches the session negotiated by the `libp2p_tls` end,
+/// whose host key is `remote`.
+fn assert_metadata<C>(ours: &SecuredConnection<C>, theirs: &TlsStream<C>, remote: &Keypair) {
+    let (_, theirs) = theirs.get_ref();
+
+    assert_eq!(ours.remote().public_key(), &remote.public());
+    assert_eq!(ours.remote().peer_id(), remote.public().to_peer_id());
+    assert_eq!(ours.remote().key_type(), remote.key_type());
+    assert_eq!(ours.session().alpn_protocol(), theirs.alpn_protocol());
+    assert_eq!(
+        Some(ours.session().cipher_suite()),
+        theirs.negotiated_cipher_suite().map(|suite| suite.suite())
+    );
+    assert_eq!(
+        Some(ours.session().protocol_version()),
+        theirs.protocol_version()
+    );
+}
+
+/// Asserts that both ends negotiated the same libp2p TLS 1.3 session and can exchange data.
+async fn assert_same_session<C>(dialer: &mut TlsStream<C>, listener: &mut TlsStream<C>)
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    {
+        let (_, dialer) = dialer.get_ref();
+        let (_, listener) = listener.get_ref();
+
+        assert_eq!(dialer.alpn_protocol(), Some(&b"libp2p"[..]));
+        assert_eq!(dialer.alpn_protocol(), listener.alpn_protocol());
+        assert_eq!(
+            dialer.protocol_version(),
+            Some(rustls::ProtocolVersion::TLSv1_3)
+        );
+        assert_eq!(dialer.protocol_version(), listener.protocol_version());
+        assert!(dialer.negotiated_cipher_suite().is_some());
+        assert_eq!(
+            dialer.negotiated_cipher_suite(),
+            listener.negotiated_cipher_suite()
+        );
+    }
+
+    dialer.write_all(b"ping").await.unwrap();
+    dialer.flush().await.unwrap();
+    let mut buf = [0u8; 4];
+    listener.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
@@ -0,0 +1,70 @@
+//! Test of logging TLS secrets to the file named by `SSLKEYLOGFILE`.
+//!
+//! The test sets the environment variable, which every thread of the process reads, so it runs in
+//! a test binary of its own.
+
+use crate::utils::memory::{dialer_endpoint, listener_endpoint, memory_connection};
+use futures::future;
+use libp2p_identity::Keypair;
+use p2p_tls_handshake::{secure, Config, Version};
+
+pub mod utils;
+
+#[tokio::test]
+async fn key_log_file_uses_nss_format() {
+    let path =
+        std::env::temp_dir().join(format!("p2p-tls-handshake-{}-keylog", std::process::id()));
+    std::env::set_var("SSLKEYLOGFILE", &path);
+    let dialer = Config::new(&Keypair::generate_ed25519())
+        .unwrap()
+        .with_key_log_file();
+    let listener = Config::new(&Keypair::generate_ed25519())
+        .unwrap()
+        .with_key_log_file();
+    std::env::remove_var("SSLKEYLOGFILE");
+
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            dialer,
+            dialer_endpoint(addr.clone()),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            listener,
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    dialer.expect("dialer to secure the connection");
+    listener.expect("listener to secure the connection");
+    let key_log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut lines: Vec<_> = key_log.lines().collect();
+    lines.sort_unstable();
+    let labels = [
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        "CLIENT_TRAFFIC_SECRET_0",
+        "EXPORTER_SECRET",
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        "SERVER_TRAFFIC_SECRET_0",
+    ];
+    // Both peers log the same secrets of the session.
+    assert_eq!(lines.len(), 2 * labels.len());
+    for (pair, label) in lines.chunks(2).zip(labels) {
+        assert_eq!(pair[0], pair[1]);
+        let fields: Vec<_> = pair[0].split(' ').collect();
+        assert_eq!(fields.len(), 3, "{}", pair[0]);
+        assert_eq!(fields[0], label);
+        // The 32 bytes of the client random, then the secret, both hex encoded.
+        assert_eq!(fields[1].len(), 64);
+        assert!(matches!(fields[2].len(), 64 | 96));
+        assert!(fields[1..]
+            .iter()
+            .all(|field| field.bytes().all(|b| b.is_ascii_hexdigit())));
+    }
+}
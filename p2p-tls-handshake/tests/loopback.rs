@@ -0,0 +1,1363 @@
+//! In-memory loopback tests of the TLS security upgrade.
+//!
+//! Both peers live in the same process and talk over a `MemoryTransport`, so these tests need
+//! neither network access nor the Docker Celestia network.
+
+use crate::utils::identity::keypairs;
+use crate::utils::memory::{
+    connect, dialer_endpoint, listen, listener_endpoint, memory_connection, next_event,
+    round_trip_transport, RoundTrips,
+};
+use crate::utils::upgrade::{ping_over_substream, Passthrough};
+use futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p_core::muxing::StreamMuxerBox;
+use libp2p_core::transport::{memory::Channel, MemoryTransport, Transport, TransportEvent};
+use libp2p_core::{multiaddr::Protocol, Negotiated, UpgradeInfo};
+use libp2p_identity::{KeyType, Keypair, PeerId, PublicKey};
+use multistream_select::NegotiationError;
+use p2p_tls_handshake::{
+    generate, make_client_config, secure, AllowedKeyTypes, Allowlist, Builder,
+    CertificateAlgorithm, CertificateParams, Config, Denylist, FileAllowlist,
+    InboundSecurityUpgrade, NoiseConfig, OutboundSecurityUpgrade, RotationSchedule,
+    SecuredConnection, SelectSecurityUpgrade, SessionCache, StoredCertificate, TlsUpgradeError,
+    UpgradeError, Version,
+};
+use rustls::client::ResolvesClientCert;
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{ServerName, SignatureScheme};
+use std::convert::Infallible;
+use std::iter;
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+pub mod utils;
+
+#[tokio::test]
+async fn secure_succeeds() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            Config::new(&dialer_keypair).unwrap(),
+            dialer_endpoint(addr.clone()),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            Config::new(&listener_keypair).unwrap(),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    let (dialer_peer_id, mut dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (listener_peer_id, mut listener_stream) =
+        listener.expect("listener to secure the connection");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+
+    dialer_stream.write_all(b"ping").await.unwrap();
+    dialer_stream.flush().await.unwrap();
+    let mut buf = [0u8; 4];
+    listener_stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn authenticate2_succeeds() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+
+    let (dialer, listener) = connect(
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(Config::new(&dialer_keypair).unwrap())
+            .multiplex(libp2p_yamux::Config::default()),
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(Config::new(&listener_keypair).unwrap())
+            .multiplex(libp2p_yamux::Config::default()),
+        Some(listener_peer_id),
+    )
+    .await;
+
+    let (dialer_peer_id, _) = dialer.expect("dialer to authenticate the listener");
+    let (listener_peer_id, _) = listener.expect("listener to authenticate the dialer");
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+}
+
+#[tokio::test]
+async fn authenticate2_aborts_on_peer_id_mismatch() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let unexpected_peer_id = PeerId::random();
+
+    let (dialer, _) = connect(
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(Config::new(&dialer_keypair).unwrap())
+            .multiplex(libp2p_yamux::Config::default()),
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(Config::new(&listener_keypair).unwrap())
+            .multiplex(libp2p_yamux::Config::default()),
+        Some(unexpected_peer_id),
+    )
+    .await;
+
+    let Err(err) = dialer else {
+        panic!("dialer accepted a listener with an unexpected peer ID");
+    };
+    match err
+        .left()
+        .and_then(|err| err.right())
+        .expect("a security upgrade error")
+    {
+        UpgradeError::Apply(TlsUpgradeError::PeerIdMismatch { .. }) => {}
+        e => panic!("unexpected error: {e:?}"),
+    }
+}
+
+#[tokio::test]
+async fn secure_lazy_outbound_succeeds() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            Config::new(&dialer_keypair).unwrap(),
+            dialer_endpoint(addr.clone()),
+            Version::V1Lazy,
+        ),
+        secure(
+            listener_conn,
+            Config::new(&listener_keypair).unwrap(),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    let (dialer_peer_id, mut dialer_stream) = dialer.expect("lazy dialer to secure the connection");
+    let (listener_peer_id, mut listener_stream) =
+        listener.expect("listener to secure the connection");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+
+    dialer_stream.write_all(b"ping").await.unwrap();
+    dialer_stream.flush().await.unwrap();
+    let mut buf = [0u8; 4];
+    listener_stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn secure_lazy_outbound_fails_on_unsupported_protocol() {
+    let keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, _) = future::join(
+        secure(
+            dialer_conn,
+            Config::new(&keypair).unwrap(),
+            dialer_endpoint(addr),
+            Version::V1Lazy,
+        ),
+        multistream_select::listener_select_proto(listener_conn, ["/noise"]),
+    )
+    .await;
+
+    assert!(matches!(
+        dialer,
+        Err(UpgradeError::Select(NegotiationError::Failed))
+    ));
+}
+
+#[tokio::test]
+async fn secure_outbound_fails_on_unsupported_protocol() {
+    let keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, _) = future::join(
+        secure(
+            dialer_conn,
+            Config::new(&keypair).unwrap(),
+            dialer_endpoint(addr),
+            Version::V1,
+        ),
+        multistream_select::listener_select_proto(listener_conn, ["/noise"]),
+    )
+    .await;
+
+    assert!(matches!(
+        dialer,
+        Err(UpgradeError::Select(NegotiationError::Failed))
+    ));
+}
+
+#[tokio::test]
+async fn secure_inbound_fails_on_unsupported_protocol() {
+    let keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (_, listener) = future::join(
+        multistream_select::dialer_select_proto(dialer_conn, ["/noise"], Version::V1),
+        secure(
+            listener_conn,
+            Config::new(&keypair).unwrap(),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+
+    assert!(matches!(listener, Err(UpgradeError::Select(_))));
+}
+
+#[tokio::test]
+async fn secure_supports_all_key_types() {
+    for dialer_keypair in keypairs() {
+        for listener_keypair in keypairs() {
+            let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+            let (dialer, listener) = future::join(
+                secure(
+                    dialer_conn,
+                    Config::new(&dialer_keypair).unwrap(),
+                    dialer_endpoint(addr.clone()),
+                    Version::V1,
+                ),
+                secure(
+                    listener_conn,
+                    Config::new(&listener_keypair).unwrap(),
+                    listener_endpoint(addr),
+                    Version::V1,
+                ),
+            )
+            .await;
+
+            let (dialer_peer_id, _) = dialer.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} dialer failed against {:?} listener: {e}",
+                    dialer_keypair.key_type(),
+                    listener_keypair.key_type()
+                )
+            });
+            let (listener_peer_id, _) = listener.unwrap_or_else(|e| {
+                panic!(
+                    "{:?} listener failed against {:?} dialer: {e}",
+                    listener_keypair.key_type(),
+                    dialer_keypair.key_type()
+                )
+            });
+            assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+            assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+        }
+    }
+}
+
+#[tokio::test]
+async fn secure_supports_all_certificate_algorithms() {
+    let keypair = Keypair::generate_ed25519();
+
+    for algorithm in [
+        CertificateAlgorithm::EcdsaP256Sha256,
+        CertificateAlgorithm::EcdsaP384Sha384,
+        CertificateAlgorithm::Ed25519,
+    ] {
+        let params = CertificateParams::new().with_algorithm(algorithm);
+        let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+        let (dialer, listener) = future::join(
+            secure(
+                dialer_conn,
+                Config::with_params(&keypair, &params).unwrap(),
+                dialer_endpoint(addr.clone()),
+                Version::V1,
+            ),
+            secure(
+                listener_conn,
+                Config::with_params(&keypair, &params).unwrap(),
+                listener_endpoint(addr),
+                Version::V1,
+            ),
+        )
+        .await;
+
+        dialer.unwrap_or_else(|e| panic!("{algorithm:?} dialer failed: {e}"));
+        listener.unwrap_or_else(|e| panic!("{algorithm:?} listener failed: {e}"));
+    }
+}
+
+#[tokio::test]
+async fn secure_presents_stored_certificate() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let stored =
+        StoredCertificate::generate(&listener_keypair, &CertificateParams::default()).unwrap();
+    let stored = StoredCertificate::from_pem(&listener_keypair, stored.to_pem().as_bytes())
+        .expect("stored certificate to load back");
+
+    for _ in 0..2 {
+        let (dialer_conn, listener_conn, addr) = memory_connection().await;
+        let (dialer, listener) = future::join(
+            secure(
+                dialer_conn,
+                Config::new(&dialer_keypair).unwrap(),
+                dialer_endpoint(addr.clone()),
+                Version::V1,
+            ),
+            secure(
+                listener_conn,
+                Config::with_certificate(&stored),
+                listener_endpoint(addr),
+                Version::V1,
+            ),
+        )
+        .await;
+        let (peer_id, stream) = dialer.expect("dialer to secure the connection");
+        listener.expect("listener to secure the connection");
+
+        assert_eq!(peer_id, listener_keypair.public().to_peer_id());
+        assert_eq!(stream.remote().certificate(), stored.certificate());
+    }
+}
+
+#[tokio::test]
+async fn rotation_keeps_established_streams() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let interval = Duration::from_secs(1);
+    let listener_config = Config::with_rotation(
+        &listener_keypair,
+        CertificateParams::default(),
+        RotationSchedule::new().with_interval(interval),
+    )
+    .unwrap();
+
+    let mut streams = Vec::new();
+    let mut certificates = Vec::new();
+    for round in 0..2 {
+        if round > 0 {
+            // Give the rotation thread time to replace the certificate.
+            std::thread::sleep(2 * interval);
+        }
+        let (dialer_conn, listener_conn, addr) = memory_connection().await;
+        let (dialer, listener) = future::join(
+            secure(
+                dialer_conn,
+                Config::new(&dialer_keypair).unwrap(),
+                dialer_endpoint(addr.clone()),
+                Version::V1,
+            ),
+            secure(
+                listener_conn,
+                listener_config.clone(),
+                listener_endpoint(addr),
+                Version::V1,
+            ),
+        )
+        .await;
+        let (peer_id, dialer_stream) = dialer.expect("dialer to secure the connection");
+        let (_, listener_stream) = listener.expect("listener to secure the connection");
+
+        assert_eq!(peer_id, listener_keypair.public().to_peer_id());
+        certificates.push(dialer_stream.remote().certificate().clone());
+        streams.push((dialer_stream, listener_stream));
+    }
+
+    assert_ne!(certificates[0], certificates[1]);
+    for (mut dialer_stream, mut listener_stream) in streams {
+        dialer_stream.write_all(b"ping").await.unwrap();
+        dialer_stream.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        listener_stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+}
+
+#[tokio::test]
+async fn secure_reports_connection_metadata() {
+    let dialer_keypair = Keypair::generate_ecdsa();
+    let listener_keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            Config::new(&dialer_keypair).unwrap(),
+            dialer_endpoint(addr.clone()),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            Config::new(&listener_keypair).unwrap(),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (_, listener_stream) = listener.expect("listener to secure the connection");
+
+    let remote = dialer_stream.remote();
+    assert_eq!(remote.public_key(), &listener_keypair.public());
+    assert_eq!(remote.key_type(), KeyType::Ed25519);
+    let remote = listener_stream.remote();
+    assert_eq!(remote.public_key(), &dialer_keypair.public());
+    assert_eq!(remote.key_type(), KeyType::Ecdsa);
+
+    for session in [dialer_stream.session(), listener_stream.session()] {
+        assert_eq!(session.alpn_protocol(), Some(&b"libp2p"[..]));
+        assert_eq!(session.protocol_version(), rustls::ProtocolVersion::TLSv1_3);
+    }
+    assert_eq!(
+        dialer_stream.session().cipher_suite(),
+        listener_stream.session().cipher_suite()
+    );
+}
+
+type SecureResult = Result<
+    (PeerId, SecuredConnection<Negotiated<Channel<Vec<u8>>>>),
+    UpgradeError<TlsUpgradeError>,
+>;
+
+/// Secures a fresh in-memory connection between `dialer` and `listener`.
+async fn secure_pair(dialer: Config, listener: Config) -> (SecureResult, SecureResult) {
+    secure_pair_to(dialer, listener, None).await
+}
+
+/// Secures a fresh in-memory connection between `dialer` and `listener`, where the dialer
+/// optionally expects the listener to have `peer_id`.
+async fn secure_pair_to(
+    dialer: Config,
+    listener: Config,
+    peer_id: Option<PeerId>,
+) -> (SecureResult, SecureResult) {
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+    let dialed_addr = match peer_id {
+        Some(peer_id) => addr.clone().with(Protocol::P2p(peer_id)),
+        None => addr.clone(),
+    };
+
+    future::join(
+        secure(
+            dialer_conn,
+            dialer,
+            dialer_endpoint(dialed_addr),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            listener,
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await
+}
+
+/// Returns the TLS error behind a failed handshake.
+fn tls_error(error: UpgradeError<TlsUpgradeError>) -> rustls::Error {
+    match error {
+        UpgradeError::Apply(
+            TlsUpgradeError::ClientUpgrade(e) | TlsUpgradeError::ServerUpgrade(e),
+        ) => *e
+            .into_inner()
+            .expect("a TLS error")
+            .downcast::<rustls::Error>()
+            .expect("a TLS error"),
+        e => panic!("unexpected error: {e:?}"),
+    }
+}
+
+/// Presents the same certificate chain in every handshake.
+struct ChainResolver(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for ChainResolver {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+#[tokio::test]
+async fn listener_rejects_certificate_chain_of_rustls_dialer() {
+    let (certificate, private_key) = generate(&Keypair::generate_ed25519()).unwrap();
+    let (second_certificate, _) = generate(&Keypair::generate_ed25519()).unwrap();
+    let chain = CertifiedKey::new(
+        vec![certificate, second_certificate],
+        any_supported_type(&private_key).unwrap(),
+    );
+    let mut client = make_client_config(&Keypair::generate_ed25519(), None).unwrap();
+    client.client_auth_cert_resolver = Arc::new(ChainResolver(Arc::new(chain)));
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let dial = async {
+        let (_, stream) =
+            multistream_select::dialer_select_proto(dialer_conn, ["/tls/1.0.0"], Version::V1)
+                .await?;
+        let name = ServerName::IpAddress(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        futures_rustls::TlsConnector::from(Arc::new(client))
+            .connect(name, stream)
+            .await
+    };
+    let (_, listener) = future::join(
+        dial,
+        secure(
+            listener_conn,
+            Config::new(&Keypair::generate_ed25519()).unwrap(),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+
+    let Err(error) = listener else {
+        panic!("listener accepted a certificate chain");
+    };
+    let rustls::Error::InvalidCertificate(rustls::CertificateError::Other(error)) =
+        tls_error(error)
+    else {
+        panic!("certificate chain was rejected for another reason");
+    };
+    assert!(matches!(
+        error.downcast_ref(),
+        Some(TlsUpgradeError::CertificateCount(2))
+    ));
+}
+
+#[tokio::test]
+async fn policy_admits_peers() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ecdsa();
+    let dialer_peer_id = dialer_keypair.public().to_peer_id();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+
+    let (dialer, listener) = secure_pair(
+        Config::new(&dialer_keypair)
+            .unwrap()
+            .with_policy(AllowedKeyTypes::new([KeyType::Ecdsa]))
+            .with_policy(Denylist::new([PeerId::random()])),
+        Config::new(&listener_keypair)
+            .unwrap()
+            .with_policy(Allowlist::new([dialer_peer_id]))
+            .with_policy(move |public_key: &PublicKey| public_key.to_peer_id() == dialer_peer_id),
+    )
+    .await;
+
+    assert_eq!(dialer.unwrap().0, listener_peer_id);
+    assert_eq!(listener.unwrap().0, dialer_peer_id);
+}
+
+#[tokio::test]
+async fn inbound_policy_rejects_peer_during_handshake() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+
+    let (dialer, listener) = secure_pair(
+        Config::new(&dialer_keypair).unwrap(),
+        Config::new(&listener_keypair)
+            .unwrap()
+            .with_policy(Allowlist::new([PeerId::random()])),
+    )
+    .await;
+
+    assert_eq!(
+        tls_error(listener.err().expect("listener to reject the dialer")),
+        rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)
+    );
+    // TLS 1.3 clients finish their handshake before the server verifies their certificate, so
+    // the rejection may only reach the dialer once it reads from the connection.
+    if let Ok((_, mut stream)) = dialer {
+        let mut buf = [0u8; 1];
+        let error = stream
+            .read(&mut buf)
+            .await
+            .expect_err("the rejection alert");
+        assert_eq!(
+            *error
+                .into_inner()
+                .unwrap()
+                .downcast::<rustls::Error>()
+                .unwrap(),
+            rustls::Error::AlertReceived(rustls::AlertDescription::AccessDenied)
+        );
+    }
+}
+
+#[tokio::test]
+async fn outbound_policy_rejects_peer_during_handshake() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_secp256k1();
+
+    let (dialer, listener) = secure_pair(
+        Config::new(&dialer_keypair)
+            .unwrap()
+            .with_policy(AllowedKeyTypes::new([KeyType::Ed25519])),
+        Config::new(&listener_keypair).unwrap(),
+    )
+    .await;
+
+    assert_eq!(
+        tls_error(dialer.err().expect("dialer to reject the listener")),
+        rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)
+    );
+    assert_eq!(
+        tls_error(listener.err().expect("listener to receive the rejection")),
+        rustls::Error::AlertReceived(rustls::AlertDescription::AccessDenied)
+    );
+}
+
+#[tokio::test]
+async fn policy_does_not_affect_the_configuration_it_was_cloned_from() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let base = Config::new(&listener_keypair).unwrap();
+    let deny_all = base.clone().with_policy(|_: &PublicKey| false);
+
+    let (dialer, listener) = secure_pair(Config::new(&dialer_keypair).unwrap(), base).await;
+    assert_eq!(listener.unwrap().0, dialer_keypair.public().to_peer_id());
+    assert_eq!(dialer.unwrap().0, listener_keypair.public().to_peer_id());
+
+    let (_, listener) = secure_pair(Config::new(&dialer_keypair).unwrap(), deny_all).await;
+    assert_eq!(
+        tls_error(listener.err().expect("listener to reject the dialer")),
+        rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)
+    );
+}
+
+#[tokio::test]
+async fn allowlist_reloads_while_handshakes_are_in_flight() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let dialer_peer_id = dialer_keypair.public().to_peer_id();
+    let path = std::env::temp_dir().join(format!(
+        "p2p-tls-handshake-{}-allowlist-in-flight",
+        std::process::id()
+    ));
+    std::fs::write(&path, dialer_peer_id.to_string()).unwrap();
+    let allowlist = FileAllowlist::load(&path).unwrap();
+    let listener_config = Config::new(&listener_keypair)
+        .unwrap()
+        .with_policy(allowlist.clone());
+
+    // Keep rewriting the file with the dialer and some other peer while the handshakes run.
+    let reloader = {
+        let allowlist = allowlist.clone();
+        std::thread::spawn(move || {
+            for _ in 0..50 {
+                let peers = format!("{dialer_peer_id}\n{}\n", PeerId::random());
+                std::fs::write(allowlist.path(), peers).unwrap();
+                allowlist.reload().unwrap();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        })
+    };
+    let handshakes = future::join_all((0..16).map(|_| {
+        secure_pair(
+            Config::new(&dialer_keypair).unwrap(),
+            listener_config.clone(),
+        )
+    }))
+    .await;
+    reloader.join().unwrap();
+
+    for (dialer, listener) in handshakes {
+        dialer.expect("dialer to secure the connection");
+        listener.expect("listener to admit the dialer");
+    }
+
+    std::fs::write(&path, PeerId::random().to_string()).unwrap();
+    allowlist.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let (_, listener) = secure_pair(Config::new(&dialer_keypair).unwrap(), listener_config).await;
+    assert_eq!(
+        tls_error(listener.err().expect("listener to reject the dialer")),
+        rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure)
+    );
+}
+
+#[tokio::test]
+async fn clock_decides_certificate_validity() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let not_before = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let not_after = not_before + Duration::from_secs(3600);
+    let params = CertificateParams::new().with_validity(not_before, not_after);
+    let listener_config = || Config::with_params(&listener_keypair, &params).unwrap();
+
+    // The listener certificate is within its validity period at the time of the dialer's clock.
+    let (dialer, listener) = secure_pair(
+        Config::new(&dialer_keypair)
+            .unwrap()
+            .with_clock(move || not_before + Duration::from_secs(60)),
+        listener_config(),
+    )
+    .await;
+    dialer.expect("dialer to accept the listener certificate");
+    listener.expect("listener to secure the connection");
+
+    // Two hours later it has expired.
+    let late = move || not_after + Duration::from_secs(3600);
+    let (dialer, _) = secure_pair(
+        Config::new(&dialer_keypair).unwrap().with_clock(late),
+        listener_config(),
+    )
+    .await;
+    assert!(matches!(
+        tls_error(
+            dialer
+                .err()
+                .expect("dialer to reject the expired certificate")
+        ),
+        rustls::Error::InvalidCertificate(_)
+    ));
+
+    // Unless the dialer tolerates that much clock skew.
+    let (dialer, _) = secure_pair(
+        Config::new(&dialer_keypair)
+            .unwrap()
+            .with_clock(late)
+            .with_clock_skew(Duration::from_secs(2 * 3600)),
+        listener_config(),
+    )
+    .await;
+    dialer.expect("dialer to tolerate the clock skew");
+}
+
+#[tokio::test]
+async fn clock_does_not_affect_the_configuration_it_was_cloned_from() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let not_before = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let not_after = not_before + Duration::from_secs(3600);
+    let params = CertificateParams::new().with_validity(not_before, not_after);
+    let listener_config = || Config::with_params(&listener_keypair, &params).unwrap();
+    let base = Config::new(&dialer_keypair)
+        .unwrap()
+        .with_clock(move || not_before + Duration::from_secs(60));
+    let late = base
+        .clone()
+        .with_clock(move || not_after + Duration::from_secs(3600));
+    let _tolerant = late.clone().with_clock_skew(Duration::from_secs(2 * 3600));
+
+    let (dialer, _) = secure_pair(base, listener_config()).await;
+    dialer.expect("dialer to keep its own clock");
+
+    let (dialer, _) = secure_pair(late, listener_config()).await;
+    assert!(matches!(
+        tls_error(
+            dialer
+                .err()
+                .expect("dialer to keep rejecting the expired certificate")
+        ),
+        rustls::Error::InvalidCertificate(_)
+    ));
+}
+
+#[tokio::test]
+async fn both_peers_export_the_same_keying_material() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ecdsa();
+
+    let (dialer, listener) = secure_pair(
+        Config::new(&dialer_keypair).unwrap(),
+        Config::new(&listener_keypair).unwrap(),
+    )
+    .await;
+    let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (_, listener_stream) = listener.expect("listener to secure the connection");
+
+    let export = |stream: &SecuredConnection<_>, context: Option<&[u8]>| {
+        let mut output = [0; 48];
+        stream.export_keying_material(&mut output, context).unwrap();
+        output
+    };
+    let token = export(&dialer_stream, Some(b"auth-token"));
+    assert_eq!(token, export(&listener_stream, Some(b"auth-token")));
+    assert_ne!(token, export(&listener_stream, Some(b"other-token")));
+    assert_ne!(token, export(&listener_stream, None));
+
+    // Keying material of another connection between the same peers is unrelated.
+    let (dialer, _) = secure_pair(
+        Config::new(&dialer_keypair).unwrap(),
+        Config::new(&listener_keypair).unwrap(),
+    )
+    .await;
+    let (_, other_stream) = dialer.expect("dialer to secure the connection");
+    assert_ne!(token, export(&other_stream, Some(b"auth-token")));
+}
+
+#[tokio::test]
+async fn both_peers_compute_the_same_channel_binding() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let schedule = RotationSchedule::new().with_interval(Duration::from_secs(3600));
+
+    let (dialer, listener) = secure_pair(
+        Config::new(&dialer_keypair).unwrap(),
+        Config::with_rotation(&listener_keypair, CertificateParams::new(), schedule).unwrap(),
+    )
+    .await;
+    let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (_, listener_stream) = listener.expect("listener to secure the connection");
+
+    assert_eq!(
+        dialer_stream.local_certificate(),
+        listener_stream.remote().certificate()
+    );
+    assert_eq!(
+        listener_stream.local_certificate(),
+        dialer_stream.remote().certificate()
+    );
+
+    let binding = dialer_stream.channel_binding().unwrap();
+    assert_eq!(binding, listener_stream.channel_binding().unwrap());
+
+    let mut exported = [0; 32];
+    dialer_stream
+        .export_keying_material(&mut exported, None)
+        .unwrap();
+    assert_ne!(binding, exported);
+}
+
+/// Secures a connection from `dialer` to `listener` and has the listener send some data, which
+/// delivers its session tickets to the dialer.
+async fn connect_and_resume(
+    dialer: &Config,
+    listener: &Config,
+    peer_id: PeerId,
+) -> (SecureResult, SecureResult) {
+    let (mut dialer, mut listener) =
+        secure_pair_to(dialer.clone(), listener.clone(), Some(peer_id)).await;
+
+    if let (Ok((_, dialer_stream)), Ok((_, listener_stream))) = (&mut dialer, &mut listener) {
+        listener_stream.write_all(b"ping").await.unwrap();
+        listener_stream.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        dialer_stream.read_exact(&mut buf).await.unwrap();
+    }
+
+    (dialer, listener)
+}
+
+#[tokio::test]
+async fn reconnect_resumes_session() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ecdsa();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+    let dialer_config = Config::new(&dialer_keypair).unwrap();
+    let listener_config = Config::new(&listener_keypair).unwrap();
+
+    let (dialer, listener) =
+        connect_and_resume(&dialer_config, &listener_config, listener_peer_id).await;
+    let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (_, listener_stream) = listener.expect("listener to secure the connection");
+    assert!(!dialer_stream.session().resumed());
+    assert!(!listener_stream.session().resumed());
+
+    let (dialer, listener) =
+        connect_and_resume(&dialer_config, &listener_config, listener_peer_id).await;
+    let (dialer_peer_id, resumed_dialer) = dialer.expect("dialer to resume the session");
+    let (listener_peer_id, resumed_listener) = listener.expect("listener to resume the session");
+    assert!(resumed_dialer.session().resumed());
+    assert!(resumed_listener.session().resumed());
+
+    // A resumed session carries the identities established by the full handshake.
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+    assert_eq!(
+        resumed_dialer.remote().certificate(),
+        dialer_stream.remote().certificate()
+    );
+    assert_eq!(
+        resumed_dialer.local_certificate(),
+        resumed_listener.remote().certificate()
+    );
+    assert_eq!(
+        resumed_listener.local_certificate(),
+        resumed_dialer.remote().certificate()
+    );
+    assert_eq!(
+        resumed_dialer.channel_binding().unwrap(),
+        resumed_listener.channel_binding().unwrap()
+    );
+
+    // Without an expected peer ID, nothing is resumed.
+    let (dialer, _) = secure_pair(dialer_config.clone(), listener_config.clone()).await;
+    let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+    assert!(!dialer_stream.session().resumed());
+}
+
+#[tokio::test]
+async fn resumption_refuses_different_peer_id() {
+    let dialer_config = Config::new(&Keypair::generate_ed25519()).unwrap();
+    let listener_keypair = Keypair::generate_ed25519();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+    let listener_config = Config::new(&listener_keypair).unwrap();
+
+    let (dialer, _) = connect_and_resume(&dialer_config, &listener_config, listener_peer_id).await;
+    dialer.expect("dialer to secure the connection");
+
+    // The session with the listener is not offered to another peer ID.
+    let other_peer_id = PeerId::random();
+    let (dialer, _) = connect_and_resume(&dialer_config, &listener_config, other_peer_id).await;
+    assert!(matches!(
+        dialer,
+        Err(UpgradeError::Apply(TlsUpgradeError::PeerIdMismatch { expected, found }))
+            if expected == listener_peer_id && found == other_peer_id
+    ));
+
+    // Another peer answering for the listener cannot resume its session.
+    let impostor_keypair = Keypair::generate_ed25519();
+    let impostor_config = Config::new(&impostor_keypair)
+        .unwrap()
+        .with_session_cache(SessionCache::default());
+    // It presents its own certificate instead.
+    let (dialer, _) = connect_and_resume(&dialer_config, &impostor_config, listener_peer_id).await;
+    assert!(matches!(
+        dialer,
+        Err(UpgradeError::Apply(TlsUpgradeError::PeerIdMismatch { expected, found }))
+            if expected == impostor_keypair.public().to_peer_id() && found == listener_peer_id
+    ));
+
+    // The session with the listener is still resumed.
+    let (dialer, _) = connect_and_resume(&dialer_config, &listener_config, listener_peer_id).await;
+    let (_, dialer_stream) = dialer.expect("dialer to resume the session");
+    assert!(dialer_stream.session().resumed());
+}
+
+#[tokio::test]
+async fn session_cache_is_bounded() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let cache = SessionCache::new(1, Duration::from_secs(60));
+    let dialer_config = Config::new(&dialer_keypair)
+        .unwrap()
+        .with_session_cache(cache.clone());
+    let first_keypair = Keypair::generate_ed25519();
+    let first_config = Config::new(&first_keypair).unwrap();
+    let second_keypair = Keypair::generate_ed25519();
+    let second_config = Config::new(&second_keypair).unwrap();
+    let first = first_keypair.public().to_peer_id();
+    let second = second_keypair.public().to_peer_id();
+
+    for (config, peer_id) in [(&first_config, first), (&second_config, second)] {
+        let (dialer, _) = connect_and_resume(&dialer_config, config, peer_id).await;
+        dialer.expect("dialer to secure the connection");
+    }
+    assert_eq!(cache.len(), 1);
+
+    // The sessions with the first listener were evicted.
+    let (dialer, _) = connect_and_resume(&dialer_config, &first_config, first).await;
+    assert!(!dialer.unwrap().1.session().resumed());
+
+    // Expired sessions are not resumed either.
+    let dialer_config = Config::new(&dialer_keypair)
+        .unwrap()
+        .with_session_cache(SessionCache::new(8, Duration::ZERO));
+    let (dialer, _) = connect_and_resume(&dialer_config, &first_config, first).await;
+    dialer.expect("dialer to secure the connection");
+    let (dialer, _) = connect_and_resume(&dialer_config, &first_config, first).await;
+    assert!(!dialer.unwrap().1.session().resumed());
+}
+
+#[tokio::test]
+async fn resumed_sessions_are_admitted_by_policies() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+    let admitted = Arc::new(AtomicBool::new(true));
+    let dialer_config = Config::new(&dialer_keypair).unwrap();
+    let listener_config = Config::new(&listener_keypair).unwrap().with_policy({
+        let admitted = admitted.clone();
+        move |_: &PublicKey| admitted.load(Ordering::SeqCst)
+    });
+
+    let (dialer, listener) =
+        connect_and_resume(&dialer_config, &listener_config, listener_peer_id).await;
+    dialer.expect("dialer to secure the connection");
+    listener.expect("listener to admit the dialer");
+
+    admitted.store(false, Ordering::SeqCst);
+    let (_, listener) =
+        connect_and_resume(&dialer_config, &listener_config, listener_peer_id).await;
+    assert!(matches!(
+        tls_error(
+            listener
+                .err()
+                .expect("listener to reject the resumed session")
+        ),
+        rustls::Error::InvalidCertificate(_)
+    ));
+}
+
+const YAMUX: &str = "/yamux/1.0.0";
+
+/// Authenticates with `dialer` and `listener` and multiplexes with yamux, skipping its
+/// negotiation if agreed on during the handshake, returning the results of both upgrades.
+async fn multiplex_pair(dialer: Config, listener: Config) -> (MultiplexResult, MultiplexResult) {
+    connect(
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(dialer)
+            .multiplex_early(libp2p_yamux::Config::default())
+            .boxed(),
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(listener)
+            .multiplex_early(libp2p_yamux::Config::default())
+            .boxed(),
+        None,
+    )
+    .await
+}
+
+type MultiplexResult = Result<(PeerId, StreamMuxerBox), std::io::Error>;
+
+#[tokio::test]
+async fn early_muxer_is_agreed_on_by_alpn() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let dialer_config = Config::new(&dialer_keypair)
+        .unwrap()
+        .with_early_muxers(["/mplex/6.7.0", YAMUX]);
+    let listener_config = Config::new(&listener_keypair)
+        .unwrap()
+        .with_early_muxers([YAMUX]);
+
+    let (dialer, listener) = secure_pair(dialer_config.clone(), listener_config.clone()).await;
+    let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (_, listener_stream) = listener.expect("listener to secure the connection");
+    assert_eq!(dialer_stream.early_muxer(), Some(YAMUX));
+    assert_eq!(listener_stream.early_muxer(), Some(YAMUX));
+
+    let (dialer, listener) = multiplex_pair(dialer_config, listener_config).await;
+    let (_, dialer_muxer) = dialer.expect("dialer to multiplex the connection");
+    let (_, listener_muxer) = listener.expect("listener to multiplex the connection");
+    assert_eq!(
+        &ping_over_substream(dialer_muxer, listener_muxer).await,
+        b"ping"
+    );
+}
+
+#[tokio::test]
+async fn multiplex_negotiates_despite_early_muxer() {
+    let builder = |keypair: &Keypair| {
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(Config::new(keypair).unwrap().with_early_muxers([YAMUX]))
+            .multiplex(libp2p_yamux::Config::default())
+    };
+
+    let (dialer, listener) = connect(
+        builder(&Keypair::generate_ed25519()),
+        builder(&Keypair::generate_ed25519()),
+        None,
+    )
+    .await;
+    // The multiplexer is negotiated over the connection, as with any other security upgrade.
+    let (_, dialer_muxer): (PeerId, libp2p_yamux::Muxer<Negotiated<_>>) =
+        dialer.expect("dialer to multiplex the connection");
+    let (_, listener_muxer) = listener.expect("listener to multiplex the connection");
+    assert_eq!(
+        &ping_over_substream(
+            StreamMuxerBox::new(dialer_muxer),
+            StreamMuxerBox::new(listener_muxer)
+        )
+        .await,
+        b"ping"
+    );
+}
+
+#[tokio::test]
+async fn noise_authenticates_and_multiplexes() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let builder = |keypair: &Keypair| {
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(NoiseConfig::new(keypair).unwrap())
+            .multiplex(libp2p_yamux::Config::default())
+            .boxed()
+    };
+
+    let (dialer, listener) = connect(
+        builder(&dialer_keypair),
+        builder(&listener_keypair),
+        Some(listener_keypair.public().to_peer_id()),
+    )
+    .await;
+    let (dialer_peer_id, dialer_muxer) = dialer.expect("dialer to authenticate the listener");
+    let (listener_peer_id, listener_muxer) = listener.expect("listener to authenticate the dialer");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+    assert_eq!(
+        &ping_over_substream(dialer_muxer, listener_muxer).await,
+        b"ping"
+    );
+}
+
+#[tokio::test]
+async fn early_muxer_falls_back_to_multistream_select() {
+    for (dialer_muxers, listener_muxers) in [(vec![YAMUX], vec![]), (vec![], vec![YAMUX])] {
+        let dialer_config = Config::new(&Keypair::generate_ed25519())
+            .unwrap()
+            .with_early_muxers(dialer_muxers);
+        let listener_config = Config::new(&Keypair::generate_ed25519())
+            .unwrap()
+            .with_early_muxers(listener_muxers);
+
+        let (dialer, _) = secure_pair(dialer_config.clone(), listener_config.clone()).await;
+        let (_, dialer_stream) = dialer.expect("dialer to secure the connection");
+        assert_eq!(
+            dialer_stream.session().alpn_protocol(),
+            Some(&b"libp2p"[..])
+        );
+        assert_eq!(dialer_stream.early_muxer(), None);
+
+        let (dialer, listener) = multiplex_pair(dialer_config, listener_config).await;
+        let (_, dialer_muxer) = dialer.expect("dialer to multiplex the connection");
+        let (_, listener_muxer) = listener.expect("listener to multiplex the connection");
+        assert_eq!(
+            &ping_over_substream(dialer_muxer, listener_muxer).await,
+            b"ping"
+        );
+    }
+}
+
+#[tokio::test]
+async fn early_muxer_must_be_supported_by_the_upgrade() {
+    let dialer_config = Config::new(&Keypair::generate_ed25519())
+        .unwrap()
+        .with_early_muxers(["/mplex/6.7.0"]);
+    let listener_config = Config::new(&Keypair::generate_ed25519())
+        .unwrap()
+        .with_early_muxers(["/mplex/6.7.0"]);
+
+    let (dialer, listener) = connect(
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(dialer_config)
+            .multiplex_early(libp2p_yamux::Config::default()),
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(listener_config)
+            .multiplex_early(libp2p_yamux::Config::default()),
+        None,
+    )
+    .await;
+
+    let Err(err) = dialer else {
+        panic!("dialer multiplexed a connection with an unsupported early muxer");
+    };
+    match err.right().expect("a multiplexer upgrade error") {
+        UpgradeError::Select(NegotiationError::Failed) => {}
+        e => panic!("unexpected error: {e:?}"),
+    }
+    // The listener fails the same way, unless the dialer hung up before it sent its tickets.
+    assert!(listener.is_err());
+}
+
+#[tokio::test]
+async fn early_muxer_follows_upgrades_applied_after_the_handshake() {
+    let builder = |keypair: &Keypair| {
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(Config::new(keypair).unwrap().with_early_muxers([YAMUX]))
+            .apply(Passthrough)
+            .multiplex_early(libp2p_yamux::Config::default())
+            .boxed()
+    };
+
+    let (dialer, listener) = connect(
+        builder(&Keypair::generate_ed25519()),
+        builder(&Keypair::generate_ed25519()),
+        None,
+    )
+    .await;
+    let (_, dialer_muxer) = dialer.expect("dialer to multiplex the connection");
+    let (_, listener_muxer) = listener.expect("listener to multiplex the connection");
+    assert_eq!(
+        &ping_over_substream(dialer_muxer, listener_muxer).await,
+        b"ping"
+    );
+}
+
+/// Counts the round trips the dialer waits for until its connection is authenticated, upgraded
+/// and multiplexed by builders using `version` and offering `early_muxers`.
+async fn dialer_round_trips(version: Version, early_muxers: &[&str]) -> usize {
+    let round_trips = Arc::new(RoundTrips::default());
+    let builder = |keypair: &Keypair, round_trips| {
+        Builder::new(round_trip_transport(round_trips), version)
+            .authenticate2(
+                Config::new(keypair)
+                    .unwrap()
+                    .with_early_muxers(early_muxers),
+            )
+            .apply(Passthrough)
+            .multiplex_early(libp2p_yamux::Config::default())
+    };
+    let mut dialer = builder(&Keypair::generate_ed25519(), round_trips.clone());
+    let mut listener = builder(&Keypair::generate_ed25519(), Arc::default());
+
+    let addr = listen(&mut listener).await;
+    let dial = round_trips.drive(dialer.dial(addr).unwrap());
+    let accept = async {
+        match next_event(&mut listener).await {
+            TransportEvent::Incoming { upgrade, .. } => upgrade.await,
+            _ => panic!("expected an incoming connection"),
+        }
+    };
+    // A lazy dialer is done before the listener, which then waits for it to read its answers.
+    let dialer = match future::select(pin!(dial), pin!(accept)).await {
+        future::Either::Left((dialer, _)) => dialer,
+        future::Either::Right((listener, dial)) => {
+            listener.expect("listener to upgrade the connection");
+            dial.await
+        }
+    };
+    dialer.expect("dialer to upgrade the connection");
+
+    round_trips.count()
+}
+
+#[tokio::test]
+async fn v1_waits_for_every_negotiation() {
+    // One round trip for each of the three protocol negotiations and the TLS handshake.
+    assert_eq!(dialer_round_trips(Version::V1, &[]).await, 4);
+}
+
+#[tokio::test]
+async fn v1_lazy_pipelines_every_negotiation() {
+    // Only the TLS handshake, whose response also confirms the security protocol.
+    assert_eq!(dialer_round_trips(Version::V1Lazy, &[]).await, 1);
+}
+
+#[tokio::test]
+async fn early_muxer_skips_only_the_multiplexer_negotiation() {
+    // The upgrade applied after the handshake is still negotiated.
+    assert_eq!(dialer_round_trips(Version::V1, &[YAMUX]).await, 3);
+}
+
+/// A security upgrade that takes the remote peer to be the given one, without any handshake.
+#[derive(Clone)]
+struct Insecure(PeerId);
+
+impl UpgradeInfo for Insecure {
+    type Info = &'static str;
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once("/insecure/1.0.0")
+    }
+}
+
+impl<C> InboundSecurityUpgrade<C> for Insecure {
+    type Output = C;
+    type Error = Infallible;
+    type Future = future::Ready<Result<(PeerId, C), Infallible>>;
+
+    fn secure_inbound(self, socket: C, _: Self::Info) -> Self::Future {
+        future::ok((self.0, socket))
+    }
+}
+
+impl<C> OutboundSecurityUpgrade<C> for Insecure {
+    type Output = C;
+    type Error = Infallible;
+    type Future = future::Ready<Result<(PeerId, C), Infallible>>;
+
+    fn secure_outbound(self, socket: C, _: Self::Info, _: Option<PeerId>) -> Self::Future {
+        future::ok((self.0, socket))
+    }
+}
+
+#[tokio::test]
+async fn select_security_upgrade_prefers_the_first_protocol() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let select = |keypair: &Keypair| {
+        SelectSecurityUpgrade::new(Config::new(keypair).unwrap(), Insecure(PeerId::random()))
+    };
+    let protocols: Vec<_> = select(&dialer_keypair)
+        .protocol_info()
+        .map(|protocol| AsRef::<str>::as_ref(&protocol).to_owned())
+        .collect();
+    assert_eq!(protocols, ["/tls/1.0.0", "/insecure/1.0.0"]);
+
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            select(&dialer_keypair),
+            dialer_endpoint(addr.clone()),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            select(&listener_keypair),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    let (dialer_peer_id, dialer_stream) = dialer.expect("dialer to secure the connection");
+    let (listener_peer_id, listener_stream) = listener.expect("listener to secure the connection");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+    assert!(matches!(dialer_stream, future::Either::Left(_)));
+    assert!(matches!(listener_stream, future::Either::Left(_)));
+    assert_eq!(ping(dialer_stream, listener_stream).await, *b"ping");
+}
+
+#[tokio::test]
+async fn select_security_upgrade_falls_back_to_the_second_protocol() {
+    let dialer_peer_id = PeerId::random();
+    let listener_peer_id = PeerId::random();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            SelectSecurityUpgrade::new(
+                Config::new(&Keypair::generate_ed25519()).unwrap(),
+                Insecure(listener_peer_id),
+            ),
+            dialer_endpoint(addr.clone()),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            Insecure(dialer_peer_id),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    let (peer_id, dialer_stream) = dialer.expect("dialer to fall back to the insecure upgrade");
+    let (_, listener_stream) = listener.expect("listener to secure the connection");
+
+    assert_eq!(peer_id, listener_peer_id);
+    assert!(matches!(dialer_stream, future::Either::Right(_)));
+    assert_eq!(ping(dialer_stream, listener_stream).await, *b"ping");
+}
+
+/// Sends `ping` from `dialer` to `listener`, returning what the listener read.
+async fn ping<D, L>(mut dialer: D, mut listener: L) -> [u8; 4]
+where
+    D: AsyncWrite + Unpin,
+    L: AsyncRead + Unpin,
+{
+    dialer.write_all(b"ping").await.unwrap();
+    dialer.flush().await.unwrap();
+    let mut buf = [0u8; 4];
+    listener.read_exact(&mut buf).await.unwrap();
+    buf
+}
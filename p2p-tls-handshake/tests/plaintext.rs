@@ -0,0 +1,190 @@
+//! Tests of the plaintext security upgrade, including interoperability with the upstream
+//! `libp2p-plaintext` implementation.
+//!
+//! `libp2p-plaintext` rejects `Exchange` messages longer than 100 bytes, so the interoperability
+//! tests only use Ed25519 keys.
+
+use crate::utils::memory::{connect, dialer_endpoint, listener_endpoint, memory_connection};
+use crate::utils::upgrade::{ping_over_substream, Passthrough};
+use futures::{future, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p_core::{
+    multiaddr::Protocol,
+    transport::MemoryTransport,
+    upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade},
+    Negotiated, UpgradeInfo,
+};
+use libp2p_identity::{Keypair, PeerId};
+use p2p_tls_handshake::{
+    secure, Builder, PlaintextConfig, PlaintextUpgradeError, UpgradeError, Version,
+};
+use std::error::Error;
+
+pub mod utils;
+
+#[tokio::test]
+async fn dialer_interoperates_with_libp2p_plaintext_listener() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let listener_peer_id = listener_keypair.public().to_peer_id();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        secure(
+            dialer_conn,
+            PlaintextConfig::new(&dialer_keypair),
+            dialer_endpoint(addr.with(Protocol::P2p(listener_peer_id))),
+            Version::V1,
+        ),
+        libp2p_plaintext_inbound(listener_conn, &listener_keypair),
+    )
+    .await;
+    let (dialer_peer_id, dialer_stream) = dialer.expect("dialer to exchange identities");
+    let (listener_peer_id, listener_stream) =
+        listener.expect("libp2p-plaintext listener to exchange identities");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+    assert_exchange(dialer_stream, listener_stream).await;
+}
+
+#[tokio::test]
+async fn listener_interoperates_with_libp2p_plaintext_dialer() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, listener) = future::join(
+        libp2p_plaintext_outbound(dialer_conn, &dialer_keypair),
+        secure(
+            listener_conn,
+            PlaintextConfig::new(&listener_keypair),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+    let (dialer_peer_id, dialer_stream) =
+        dialer.expect("libp2p-plaintext dialer to exchange identities");
+    let (listener_peer_id, listener_stream) = listener.expect("listener to exchange identities");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+    assert_exchange(dialer_stream, listener_stream).await;
+}
+
+#[tokio::test]
+async fn dialer_aborts_on_peer_id_mismatch() {
+    let listener_keypair = Keypair::generate_ed25519();
+    let unexpected_peer_id = PeerId::random();
+    let (dialer_conn, listener_conn, addr) = memory_connection().await;
+
+    let (dialer, _) = future::join(
+        secure(
+            dialer_conn,
+            PlaintextConfig::new(&Keypair::generate_ed25519()),
+            dialer_endpoint(addr.clone().with(Protocol::P2p(unexpected_peer_id))),
+            Version::V1,
+        ),
+        secure(
+            listener_conn,
+            PlaintextConfig::new(&listener_keypair),
+            listener_endpoint(addr),
+            Version::V1,
+        ),
+    )
+    .await;
+
+    match dialer {
+        Err(UpgradeError::Apply(PlaintextUpgradeError::PeerIdMismatch { expected, found })) => {
+            assert_eq!(expected, listener_keypair.public().to_peer_id());
+            assert_eq!(found, unexpected_peer_id);
+        }
+        Err(e) => panic!("unexpected error: {e:?}"),
+        Ok(_) => panic!("dialer accepted a listener with an unexpected peer ID"),
+    }
+}
+
+#[tokio::test]
+async fn plaintext_upgrades_and_multiplexes() {
+    let dialer_keypair = Keypair::generate_ed25519();
+    let listener_keypair = Keypair::generate_ed25519();
+    let builder = |keypair: &Keypair| {
+        Builder::new(MemoryTransport::default(), Version::V1)
+            .authenticate2(PlaintextConfig::new(keypair))
+            .apply(Passthrough)
+            .multiplex(libp2p_yamux::Config::default())
+            .boxed()
+    };
+
+    let (dialer, listener) = connect(
+        builder(&dialer_keypair),
+        builder(&listener_keypair),
+        Some(listener_keypair.public().to_peer_id()),
+    )
+    .await;
+    let (dialer_peer_id, dialer_muxer) = dialer.expect("dialer to exchange identities");
+    let (listener_peer_id, listener_muxer) = listener.expect("listener to exchange identities");
+
+    assert_eq!(dialer_peer_id, listener_keypair.public().to_peer_id());
+    assert_eq!(listener_peer_id, dialer_keypair.public().to_peer_id());
+    assert_eq!(
+        &ping_over_substream(dialer_muxer, listener_muxer).await,
+        b"ping"
+    );
+}
+
+type Libp2pPlaintextOutput<C> =
+    Result<(PeerId, libp2p_plaintext::Output<Negotiated<C>>), Box<dyn Error>>;
+
+/// Negotiates `/plaintext/2.0.0` and exchanges identities on an inbound connection with
+/// `libp2p_plaintext`.
+async fn libp2p_plaintext_inbound<C>(conn: C, keypair: &Keypair) -> Libp2pPlaintextOutput<C>
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let config = libp2p_plaintext::Config::new(keypair);
+    let (info, stream) =
+        multistream_select::listener_select_proto(conn, config.protocol_info()).await?;
+
+    Ok(config.upgrade_inbound(stream, info).await?)
+}
+
+/// Negotiates `/plaintext/2.0.0` and exchanges identities on an outbound connection with
+/// `libp2p_plaintext`.
+async fn libp2p_plaintext_outbound<C>(conn: C, keypair: &Keypair) -> Libp2pPlaintextOutput<C>
+where
+    C: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let config = libp2p_plaintext::Config::new(keypair);
+    let (info, stream) =
+        multistream_select::dialer_select_proto(conn, config.protocol_info(), Version::V1).await?;
+
+    Ok(config.upgrade_outbound(stream, info).await?)
+}
+
+/// Asserts that both ends exchange data in both directions.
+async fn assert_exchange<D, L>(mut dialer: D, mut listener: L)
+where
+    D: AsyncRead + AsyncWrite + Unpin,
+    L: AsyncRead + AsyncWrite + Unpin,
+{
+    let (_, received) = future::join(
+        async {
+            dialer.write_all(b"ping").await.unwrap();
+            dialer.flush().await.unwrap();
+        },
+        async {
+            let mut buf = [0; 4];
+            listener.read_exact(&mut buf).await.unwrap();
+            listener.write_all(b"pong").await.unwrap();
+            listener.flush().await.unwrap();
+            buf
+        },
+    )
+    .await;
+    assert_eq!(&received, b"ping");
+
+    let mut buf = [0; 4];
+    dialer.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+}
@@ -0,0 +1,13 @@
+use libp2p_identity::Keypair;
+
+/// One identity keypair of every key type supported by `libp2p_identity`.
+pub fn keypairs() -> Vec<Keypair> {
+    let mut rsa_pkcs8 = include_bytes!("../data/rsa-2048.pk8").to_vec();
+
+    vec![
+        Keypair::generate_ed25519(),
+        Keypair::generate_ecdsa(),
+        Keypair::generate_secp256k1(),
+        Keypair::rsa_from_pkcs8(&mut rsa_pkcs8).expect("valid RSA test key"),
+    ]
+}
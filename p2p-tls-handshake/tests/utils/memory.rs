@@ -0,0 +1,181 @@
+use futures::{future, AsyncRead, AsyncWrite, Future};
+use libp2p_core::{
+    multiaddr::Protocol,
+    transport::{
+        memory::{Channel, MemoryTransportError},
+        ListenerId, MemoryTransport, Transport, TransportEvent,
+    },
+    ConnectedPoint, Endpoint, Multiaddr,
+};
+use libp2p_identity::PeerId;
+use std::io;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+pub fn dialer_endpoint(address: Multiaddr) -> ConnectedPoint {
+    ConnectedPoint::Dialer {
+        address,
+        role_override: Endpoint::Dialer,
+    }
+}
+
+pub fn listener_endpoint(local_addr: Multiaddr) -> ConnectedPoint {
+    ConnectedPoint::Listener {
+        send_back_addr: local_addr.clone(),
+        local_addr,
+    }
+}
+
+/// Polls the transport for its next event.
+pub async fn next_event<T>(transport: &mut T) -> TransportEvent<T::ListenerUpgrade, T::Error>
+where
+    T: Transport + Unpin,
+{
+    future::poll_fn(|cx| Pin::new(&mut *transport).poll(cx)).await
+}
+
+/// Starts listening on a fresh `/memory` address and returns that address.
+pub async fn listen<T>(transport: &mut T) -> Multiaddr
+where
+    T: Transport + Unpin,
+    T::Error: std::fmt::Debug,
+{
+    transport
+        .listen_on(ListenerId::next(), Protocol::Memory(0).into())
+        .expect("memory transport to listen");
+
+    match next_event(transport).await {
+        TransportEvent::NewAddress { listen_addr, .. } => listen_addr,
+        _ => panic!("expected a new listen address"),
+    }
+}
+
+/// Opens a raw in-memory connection, returning the dialer and listener ends and the listen address.
+pub async fn memory_connection() -> (Channel<Vec<u8>>, Channel<Vec<u8>>, Multiaddr) {
+    let mut listener = MemoryTransport::default();
+    let addr = listen(&mut listener).await;
+
+    let dial = MemoryTransport::default().dial(addr.clone()).unwrap();
+    let accept = async {
+        match next_event(&mut listener).await {
+            TransportEvent::Incoming { upgrade, .. } => upgrade.await,
+            _ => panic!("expected an incoming connection"),
+        }
+    };
+    let (dialer, listener) = future::join(dial, accept).await;
+
+    (dialer.unwrap(), listener.unwrap(), addr)
+}
+
+/// Connects `dialer` to `listener`, optionally appending the expected `/p2p` peer ID to the
+/// dialed address, and returns the results of both upgrades.
+pub async fn connect<T>(
+    mut dialer: T,
+    mut listener: T,
+    peer_id: Option<PeerId>,
+) -> (Result<T::Output, T::Error>, Result<T::Output, T::Error>)
+where
+    T: Transport + Unpin,
+    T::Error: std::fmt::Debug,
+{
+    let mut addr = listen(&mut listener).await;
+    if let Some(peer_id) = peer_id {
+        addr.push(Protocol::P2p(peer_id));
+    }
+
+    let dial = dialer.dial(addr).unwrap();
+    let accept = async {
+        match next_event(&mut listener).await {
+            TransportEvent::Incoming { upgrade, .. } => upgrade.await,
+            _ => panic!("expected an incoming connection"),
+        }
+    };
+
+    future::join(dial, accept).await
+}
+
+/// Counts the round trips of the connections of a [`round_trip_transport`], i.e. the times their
+/// owner waits for the remote peer after writing to it.
+#[derive(Default)]
+pub struct RoundTrips {
+    wrote: AtomicBool,
+    count: AtomicUsize,
+}
+
+impl RoundTrips {
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Drives `future`, which uses a counted connection, counting a round trip whenever it waits
+    /// after a write.
+    pub async fn drive<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        future::poll_fn(|cx| {
+            let poll = future.as_mut().poll(cx);
+            if poll.is_pending() && self.wrote.swap(false, Ordering::SeqCst) {
+                self.count.fetch_add(1, Ordering::SeqCst);
+            }
+            poll
+        })
+        .await
+    }
+}
+
+/// A `MemoryTransport` whose connections record their writes in `round_trips`.
+pub fn round_trip_transport(
+    round_trips: Arc<RoundTrips>,
+) -> impl Transport<
+    Output = Counted<Channel<Vec<u8>>>,
+    Error = MemoryTransportError,
+    Dial = impl Future<Output = CountedResult>,
+    ListenerUpgrade = impl Future<Output = CountedResult>,
+> + Unpin {
+    MemoryTransport::default().map(move |inner, _| Counted {
+        inner,
+        round_trips: round_trips.clone(),
+    })
+}
+
+type CountedResult = Result<Counted<Channel<Vec<u8>>>, MemoryTransportError>;
+
+/// A connection whose writes are recorded in [`RoundTrips`].
+pub struct Counted<C> {
+    inner: C,
+    round_trips: Arc<RoundTrips>,
+}
+
+impl<C: AsyncRead + Unpin> AsyncRead for Counted<C> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<C: AsyncWrite + Unpin> AsyncWrite for Counted<C> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        if n > 0 {
+            self.round_trips.wrote.store(true, Ordering::SeqCst);
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,6 @@
+// Not every test binary uses every helper.
+#![allow(dead_code)]
+
+pub mod identity;
+pub mod memory;
+pub mod upgrade;
@@ -0,0 +1,79 @@
+use futures::{future, AsyncReadExt, AsyncWriteExt};
+use libp2p_core::muxing::{StreamMuxerBox, StreamMuxerExt};
+use libp2p_core::upgrade::{InboundConnectionUpgrade, OutboundConnectionUpgrade};
+use libp2p_core::UpgradeInfo;
+use std::convert::Infallible;
+use std::iter;
+use std::pin::pin;
+use std::task::Poll;
+
+/// An upgrade that passes the connection through, once negotiated.
+#[derive(Clone)]
+pub struct Passthrough;
+
+impl UpgradeInfo for Passthrough {
+    type Info = &'static str;
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once("/passthrough/1.0.0")
+    }
+}
+
+impl<C> InboundConnectionUpgrade<C> for Passthrough {
+    type Output = C;
+    type Error = Infallible;
+    type Future = future::Ready<Result<C, Infallible>>;
+
+    fn upgrade_inbound(self, socket: C, _: Self::Info) -> Self::Future {
+        future::ok(socket)
+    }
+}
+
+impl<C> OutboundConnectionUpgrade<C> for Passthrough {
+    type Output = C;
+    type Error = Infallible;
+    type Future = future::Ready<Result<C, Infallible>>;
+
+    fn upgrade_outbound(self, socket: C, _: Self::Info) -> Self::Future {
+        future::ok(socket)
+    }
+}
+
+/// Sends `ping` from `dialer` to `listener` over a new substream, driving both multiplexers
+/// meanwhile.
+pub async fn ping_over_substream(
+    mut dialer: StreamMuxerBox,
+    mut listener: StreamMuxerBox,
+) -> [u8; 4] {
+    let mut outbound = future::poll_fn(|cx| dialer.poll_outbound_unpin(cx))
+        .await
+        .expect("dialer to open a substream");
+    let send = async {
+        outbound.write_all(b"ping").await.unwrap();
+        outbound.flush().await.unwrap();
+        future::pending::<()>().await
+    };
+    let drive_dialer = future::poll_fn(|cx| {
+        let _ = dialer.poll_unpin(cx);
+        Poll::<()>::Pending
+    });
+    let receive = async {
+        let mut inbound = future::poll_fn(|cx| listener.poll_inbound_unpin(cx))
+            .await
+            .expect("listener to accept the substream");
+        let mut buf = [0u8; 4];
+        let read = inbound.read_exact(&mut buf);
+        let drive_listener = future::poll_fn(|cx| {
+            let _ = listener.poll_unpin(cx);
+            Poll::<()>::Pending
+        });
+        future::select(pin!(read), pin!(drive_listener)).await;
+        buf
+    };
+
+    match future::select(pin!(receive), pin!(future::join(send, drive_dialer))).await {
+        future::Either::Left((buf, _)) => buf,
+        future::Either::Right(_) => unreachable!("the dialer never completes"),
+    }
+}
@@ -2,7 +2,8 @@ use celestia_types::p2p;
 use futures::StreamExt;
 
 use libp2p::{
-    swarm::{dummy, SwarmEvent},
+    core::upgrade::Version,
+    swarm::{dummy, Swarm, SwarmEvent},
     tcp, tls, yamux, SwarmBuilder,
 };
 use tokio::{
@@ -11,24 +12,20 @@ use tokio::{
     time::{sleep, Duration},
 };
 
-/// Creates a generic P2P node that uses the TLS handshake implemented in the `p2p-tls-handshake` crate.
-pub async fn generic_p2p_node() -> anyhow::Result<p2p::AddrInfo> {
-    let mut swarm = SwarmBuilder::with_new_identity()
-        .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            // TODO: The goal is to replace the following with p2p_tls_handshake::Config::new
-            // The latter is being merged upstream in https://github.com/libp2p/rust-libp2p/pull/4864
-            // where at the moment there are only some details to complete.
-            // Initially, the implementation of the TLS handshake was mainly reduced to defining the
-            // Config representation (using rustls) and implementing the InboundConnectionUpgrade
-            // and OutboundConnectionUpgrade traits for this struct (see p2p_tls_handshake::Config).
-            tls::Config::new,
-            yamux::Config::default,
-        )?
-        .with_behaviour(|_| dummy::Behaviour)?
-        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_millis(500)))
-        .build();
+/// The TLS handshake used to secure the connections of a [`generic_p2p_node`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Handshake {
+    /// The security upgrade implemented in the `p2p-tls-handshake` crate, which aborts the
+    /// handshake itself on a peer ID mismatch.
+    #[default]
+    P2pTls,
+    /// The upstream `libp2p::tls` connection upgrade.
+    Libp2pTls,
+}
+
+/// Creates a generic P2P node whose connections are secured with the given TLS `handshake`.
+pub async fn generic_p2p_node(handshake: Handshake) -> anyhow::Result<p2p::AddrInfo> {
+    let mut swarm = build_swarm(handshake)?;
 
     let local_peer_id = *swarm.local_peer_id();
     tracing::info!("→ Local peer id: {local_peer_id:?}");
@@ -69,3 +66,32 @@ pub async fn generic_p2p_node() -> anyhow::Result<p2p::AddrInfo> {
 
     Ok(addr)
 }
+
+fn build_swarm(handshake: Handshake) -> anyhow::Result<Swarm<dummy::Behaviour>> {
+    let swarm = match handshake {
+        Handshake::P2pTls => SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_other_transport(|keypair| {
+                let tcp = tcp::tokio::Transport::new(tcp::Config::default());
+                let transport = p2p_tls_handshake::Builder::new(tcp, Version::V1Lazy)
+                    .authenticate2(p2p_tls_handshake::Config::new(keypair)?)
+                    .multiplex(yamux::Config::default());
+                Ok::<_, Box<dyn std::error::Error + Send + Sync>>(transport)
+            })?
+            .with_behaviour(|_| dummy::Behaviour)?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_millis(500)))
+            .build(),
+        Handshake::Libp2pTls => SwarmBuilder::with_new_identity()
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                tls::Config::new,
+                yamux::Config::default,
+            )?
+            .with_behaviour(|_| dummy::Behaviour)?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_millis(500)))
+            .build(),
+    };
+
+    Ok(swarm)
+}
@@ -3,15 +3,25 @@
 use crate::utils::client::{new_test_client, AuthLevel};
 use celestia_rpc::prelude::*;
 use celestia_types::p2p;
-use p2p_tls_peer::generic_p2p_node;
+use p2p_tls_peer::{generic_p2p_node, Handshake};
 use tokio::time::{sleep, Duration};
 
 pub mod utils;
 
 #[tokio::test]
 async fn add_remove_peer_test() {
+    add_remove_peer(Handshake::P2pTls).await;
+}
+
+#[tokio::test]
+async fn add_remove_peer_libp2p_tls_test() {
+    // same as `add_remove_peer_test`, but against the upstream `libp2p::tls` handshake
+    add_remove_peer(Handshake::Libp2pTls).await;
+}
+
+async fn add_remove_peer(handshake: Handshake) {
     // add and then remove a peer, testing outputs from `p2p.Peers` and `p2p.Connectedness`
-    let addr_info = generic_p2p_node()
+    let addr_info = generic_p2p_node(handshake)
         .await
         .expect("failed to spin up second node");
     let client = new_test_client(AuthLevel::Admin).await.unwrap();
@@ -67,7 +77,7 @@ async fn protect_unprotect_test() {
     const PROTECT_TAG: &str = "test-tag";
     const ANOTHER_PROTECT_TAG: &str = "test-tag-2";
 
-    let addr_info = generic_p2p_node()
+    let addr_info = generic_p2p_node(Handshake::P2pTls)
         .await
         .expect("failed to spin up second node");
     let client = new_test_client(AuthLevel::Admin).await.unwrap();
@@ -117,7 +127,7 @@ async fn protect_unprotect_test() {
 
 #[tokio::test]
 async fn peer_block_unblock_test() {
-    let addr_info = generic_p2p_node()
+    let addr_info = generic_p2p_node(Handshake::P2pTls)
         .await
         .expect("failed to spin up second node");
     let client = new_test_client(AuthLevel::Admin).await.unwrap();
@@ -155,7 +165,7 @@ async fn peer_block_unblock_test() {
 
 #[tokio::test]
 async fn peer_info_test() {
-    let addr_info = generic_p2p_node()
+    let addr_info = generic_p2p_node(Handshake::P2pTls)
         .await
         .expect("failed to spin up second node");
     let client = new_test_client(AuthLevel::Admin).await.unwrap();